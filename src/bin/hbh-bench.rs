@@ -0,0 +1,128 @@
+//! Times the ticks of the simulation with many bullets on screen
+//!
+//! Usage: `hbh-bench [bullets] [ticks]`, 10000 bullets for 600 ticks by default. The world is kept
+//! full: the bullets leaving the play area or hitting the player are replaced at random positions,
+//! and the player shoots at a row of enemies that never die. Exits with an error if the mean tick
+//! takes longer than a tick of real time.
+
+use std::{
+	env,
+	process::ExitCode,
+	rc::Rc,
+	time::{Duration, Instant},
+};
+
+use cgmath::{Point2, Vector2};
+use holy_bullet_hell::{
+	clock::{TICK, TICKS_PER_SEC},
+	coords::Shape,
+	enemy::{EnemyDef, EnemyMovement, EnemyTypes},
+	game::{Inputs, WORLD_SIZE},
+	gameplay::{Enemy, ProjType, World},
+	level::Level,
+	projectiles::Projectile,
+};
+
+const ENEMIES: usize = 8;
+
+/// Xorshift, the benchmark is the same from one run to the other
+struct Rng(u64);
+
+impl Rng {
+	/// Number between 0 and 1
+	fn next(&mut self) -> f32 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		(self.0 >> 40) as f32 / (1u64 << 24) as f32
+	}
+}
+
+fn main() -> ExitCode {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let parse = |i: usize, default: usize| match args.get(i) {
+		Some(arg) => arg.parse().ok(),
+		None => Some(default),
+	};
+	let (Some(bullets), Some(ticks)) = (parse(0, 10_000), parse(1, 600)) else {
+		eprintln!("Usage: hbh-bench [bullets] [ticks]");
+		return ExitCode::FAILURE;
+	};
+	if cfg!(debug_assertions) {
+		eprintln!("Debug build, run with --release for meaningful timings");
+	}
+
+	let level = Level {
+		id: 0,
+		file: "bench".into(),
+		name: Rc::new("Benchmark".into()),
+		meta: Default::default(),
+		unlock: vec![],
+		event_list: vec![],
+	};
+	let mut world = World::start(WORLD_SIZE, &level, Rc::new(EnemyTypes::default()));
+	world.player.hp = u32::MAX;
+	let def = Rc::new(EnemyDef {
+		name: "target".into(),
+		size: (32., 32.).into(),
+		hitbox: Shape::Circle { radius: 16. },
+		hp: f32::INFINITY,
+		sprite: Point2::new(0, 0),
+		movement: EnemyMovement::Down,
+		pattern: None,
+		score: 0,
+	});
+	for i in 0..ENEMIES {
+		let x = WORLD_SIZE.w * (i as f32 + 0.5) / ENEMIES as f32;
+		world
+			.enemies
+			.push(Enemy::spawn(Point2::new(x, 40.), def.clone(), None, 0));
+	}
+
+	let inputs = Inputs { shoot: true, ..Default::default() };
+	let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+	let mut total = Duration::ZERO;
+	let mut worst = Duration::ZERO;
+	for _ in 0..ticks {
+		let projectiles = &world.projectiles;
+		let enemy_bullets = projectiles
+			.slots()
+			.filter(|&slot| !matches!(projectiles.variant[slot], ProjType::PlayerShoot))
+			.count();
+		for _ in enemy_bullets..bullets {
+			let pos = Point2::new(rng.next() * WORLD_SIZE.w, rng.next() * WORLD_SIZE.h);
+			let angle = rng.next() * std::f32::consts::TAU;
+			let vel = Vector2::new(angle.cos(), angle.sin()) * (0.5 + rng.next() * 1.5);
+			let variant = if rng.next() < 0.5 {
+				ProjType::Basic
+			} else {
+				ProjType::Aimed
+			};
+			world.projectiles.push(Projectile::new(pos, vel, variant));
+		}
+		let start = Instant::now();
+		world.tick(&inputs);
+		let elapsed = start.elapsed();
+		total += elapsed;
+		worst = worst.max(elapsed);
+	}
+
+	let mean = total / ticks.max(1) as u32;
+	println!(
+		"{bullets} bullets, {ticks} ticks: {:.3} ms per tick on average, {:.3} ms at worst",
+		mean.as_secs_f64() * 1000.,
+		worst.as_secs_f64() * 1000.
+	);
+	println!(
+		"{:.0} ticks per second, {} needed, {} hit(s) on the enemies and {} on the player",
+		1. / mean.as_secs_f64().max(f64::MIN_POSITIVE),
+		TICKS_PER_SEC,
+		world.shots_hit,
+		world.player_hits
+	);
+	if mean > TICK {
+		ExitCode::FAILURE
+	} else {
+		ExitCode::SUCCESS
+	}
+}
@@ -0,0 +1,217 @@
+//! Checks level files without launching the game
+//!
+//! Usage: `hbh-check <level file | directory>...`, exits with an error if a level is invalid.
+//! The enemy types are read from the `enemies` directory of the working directory.
+
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+use cgmath::Point2;
+use holy_bullet_hell::{
+	enemy::{EnemyTypes, ENEMY_DIR},
+	game::WORLD_SIZE,
+	gameplay::{EventType, Trigger},
+	level::{is_level_file, Level, LevelError},
+	path::EnemyPath,
+};
+
+fn main() -> ExitCode {
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.is_empty() {
+		eprintln!("Usage: hbh-check <level file | directory>...");
+		return ExitCode::FAILURE;
+	}
+	let enemy_types = match EnemyTypes::load(Path::new(ENEMY_DIR)) {
+		Ok(enemy_types) => enemy_types,
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	let mut paths = vec![];
+	for arg in &args {
+		let path = PathBuf::from(arg);
+		if path.is_dir() {
+			paths.extend(level_files(&path));
+		} else {
+			paths.push(path);
+		}
+	}
+
+	let mut n_invalid = 0;
+	for path in &paths {
+		if !check_level(path, &enemy_types) {
+			n_invalid += 1;
+		}
+	}
+	println!("{} level(s) checked, {n_invalid} invalid", paths.len());
+	if n_invalid > 0 {
+		ExitCode::FAILURE
+	} else {
+		ExitCode::SUCCESS
+	}
+}
+
+/// Level files of a directory, sorted by name
+fn level_files(dir: &Path) -> Vec<PathBuf> {
+	let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+		Ok(entries) => entries
+			.filter_map(|entry| Some(entry.ok()?.path()))
+			.filter(|path| is_level_file(path))
+			.collect(),
+		Err(err) => {
+			eprintln!("{}: {err}", dir.display());
+			vec![]
+		},
+	};
+	paths.sort();
+	paths
+}
+
+/// Whether an enemy spawned at `pos` reaches the play area: enemies spawned above it move down
+/// into it, the others never show up unless a point of their path is in it
+fn shows_up(pos: Point2<f32>, path: Option<&EnemyPath>) -> bool {
+	let inside = |x: f32, y: f32| (0. ..=WORLD_SIZE.w).contains(&x) && y <= WORLD_SIZE.h;
+	let on_path = path.is_some_and(|path| {
+		path
+			.segments
+			.iter()
+			.flat_map(|segment| segment.points())
+			.any(|point| inside(pos.x + point.x, pos.y + point.y))
+	});
+	inside(pos.x, pos.y) || on_path
+}
+
+/// Prints the errors or the summary of the level, returns whether it is valid
+fn check_level(path: &Path, enemy_types: &EnemyTypes) -> bool {
+	let level = match Level::load(path, 0, enemy_types) {
+		Ok(level) => level,
+		Err(err) => {
+			eprintln!("{}", LevelError::Parse(err));
+			return false;
+		},
+	};
+	let report = level.validate();
+	if !report.errors.is_empty() {
+		eprintln!("{}: {}", path.display(), LevelError::Events(report.errors));
+		return false;
+	}
+
+	if level.name.is_empty() {
+		println!("{}", path.display());
+	} else {
+		println!("{}: {}", path.display(), level.name);
+	}
+	let mut enemy_counts: Vec<(&str, usize)> = vec![];
+	let mut bosses = vec![];
+	let mut outside = vec![];
+	for evt in &level.event_list {
+		let (pos, name, path) = match &evt.variant {
+			EventType::_SpawnEnemy(pos, name, path) => {
+				match enemy_counts.iter_mut().find(|(counted, _)| counted == name) {
+					Some((_, count)) => *count += 1,
+					None => enemy_counts.push((name, 1)),
+				}
+				(pos, name.clone(), path.as_deref())
+			},
+			EventType::_SpawnBoss(pos, def) => {
+				bosses.push(format!("{} ({} phases)", def.name, def.phases.len()));
+				(pos, def.name.clone(), None)
+			},
+		};
+		if !shows_up(*pos, path) {
+			outside.push(format!("event {} ({name} at {}, {})", evt.id, pos.x, pos.y));
+		}
+	}
+	let enemy_counts: Vec<String> = enemy_counts
+		.iter()
+		.map(|(name, count)| format!("{count} {name}"))
+		.collect();
+	println!("  enemies: {}", enemy_counts.join(", "));
+	if !bosses.is_empty() {
+		println!("  bosses: {}", bosses.join(", "));
+	}
+	// Conditions can happen later than the earliest time used by the report
+	let conditional = level.event_list.iter().any(|evt| {
+		evt.trigger
+			.as_ref()
+			.is_some_and(|(trigger, _)| !matches!(trigger, Trigger::Event(_)))
+	});
+	let at_least = if conditional { "at least " } else { "" };
+	println!(
+		"  duration: {at_least}{:.2}s",
+		report.duration().as_secs_f32()
+	);
+	for spawn in outside {
+		println!("  warning: spawn outside of the play area, {spawn}");
+	}
+	for warning in &report.warnings {
+		println!("  warning: {warning}");
+	}
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use holy_bullet_hell::path::PathSegment;
+
+	/// Directory only used by one test of this run, removed with its files when dropped
+	struct TestDir(PathBuf);
+
+	impl TestDir {
+		fn new(test: &str) -> TestDir {
+			let dir = env::temp_dir().join(format!("hbh-check-{}-{test}", std::process::id()));
+			fs::create_dir_all(&dir).unwrap();
+			TestDir(dir)
+		}
+	}
+
+	impl Drop for TestDir {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+
+	#[test]
+	fn shipped_levels_are_valid() {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		// Wave files and the campaign manifest aren't levels
+		let paths = level_files(Path::new("levels"));
+		let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap()).collect();
+		assert_eq!(names, ["level1.hbh", "level2.hbh", "level3.hbh"]);
+		assert!(paths.iter().all(|path| check_level(path, &enemy_types)));
+	}
+
+	#[test]
+	fn invalid_levels_fail() {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		let dir = TestDir::new("invalid_levels_fail");
+		for (name, content) in [
+			("parse.hbh", "@spawn-enemy ghost 1 400 -25 -"),
+			(
+				"cycle.hbh",
+				"@spawn-enemy basic 1 400 -25 1\n@spawn-enemy basic 1 400 -25 0",
+			),
+		] {
+			let path = dir.0.join(name);
+			fs::write(&path, content).unwrap();
+			assert!(!check_level(&path, &enemy_types), "{name}");
+		}
+	}
+
+	#[test]
+	fn spawns_outside_of_the_play_area() {
+		assert!(shows_up(Point2::new(400., -25.), None));
+		assert!(!shows_up(Point2::new(-50., 100.), None));
+		assert!(!shows_up(Point2::new(400., WORLD_SIZE.h + 1.), None));
+		let path = EnemyPath {
+			name: "enter".into(),
+			segments: vec![PathSegment::Line { to: Point2::new(100., 0.), speed: 60. }],
+		};
+		assert!(shows_up(Point2::new(-50., 100.), Some(&path)));
+	}
+}
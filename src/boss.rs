@@ -0,0 +1,277 @@
+use cgmath::{Point2, Vector2, Zero};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+	clock::{duration_to_ticks, SimClock},
+	coords::{Dimensions, RectF, Shape},
+	enemy::ShotPattern,
+	gameplay::{Cooldown, DT_60},
+	projectiles::Projectile,
+};
+
+/// Height at which bosses stop after entering the screen
+const BOSS_HOME_Y: f32 = 120.;
+const BOSS_SIZE: Dimensions<f32> = Dimensions { w: 96., h: 96. };
+pub const BOSS_SCORE: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BossMovement {
+	/// Stays where it entered
+	Hover,
+	/// Goes from one side of the screen to the other
+	Sweep,
+	/// Follows the player horizontally
+	Chase,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BossPhase {
+	/// HP of the boss spent in this phase
+	pub hp: f32,
+	/// The next phase starts after this time, even if the HP of the phase isn't depleted
+	pub timeout: Option<Duration>,
+	pub movement: BossMovement,
+	pub pattern: ShotPattern,
+	/// Time between two shots
+	pub fire_interval: Duration,
+}
+
+/// Boss as written in the level file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BossDef {
+	pub name: String,
+	/// Shape hit by the shots of the player, a box of the size of the sprite by default
+	#[serde(default)]
+	pub hitbox: Option<Shape>,
+	pub phases: Vec<BossPhase>,
+}
+
+impl BossDef {
+	pub fn max_hp(&self) -> f32 {
+		self.phases.iter().map(|phase| phase.hp).sum()
+	}
+
+	/// HP at which the phase ends, the HP of the phases after it
+	pub fn threshold(&self, phase: usize) -> f32 {
+		self.phases[phase + 1..].iter().map(|phase| phase.hp).sum()
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Boss {
+	pub def: BossDef,
+	pub pos: Point2<f32>,
+	pub prev_pos: Point2<f32>,
+	vel: Vector2<f32>,
+	pub size: Dimensions<f32>,
+	pub hitbox: Shape,
+	pub hp: f32,
+	pub phase: usize,
+	/// Tick at which the current phase began
+	phase_begin: u64,
+	fire_cd: Cooldown,
+	/// The boss moves down until it reaches its place before starting its first phase
+	entered: bool,
+	/// Id of the event that spawned the boss
+	pub spawned_by: u32,
+}
+
+impl Boss {
+	pub fn spawn(pos: Point2<f32>, def: BossDef, spawned_by: u32, clock: &SimClock) -> Boss {
+		let fire_cd = Cooldown::with_duration(def.phases[0].fire_interval);
+		Boss {
+			pos,
+			prev_pos: pos,
+			vel: Vector2::zero(),
+			size: BOSS_SIZE,
+			hitbox: def.hitbox.unwrap_or(Shape::Aabb(BOSS_SIZE)),
+			hp: def.max_hp(),
+			phase: 0,
+			phase_begin: clock.now(),
+			fire_cd,
+			entered: false,
+			spawned_by,
+			def,
+		}
+	}
+
+	pub fn is_dead(&self) -> bool {
+		self.hp <= 0.
+	}
+
+	pub fn get_shot(&mut self, damage: f32) {
+		if self.entered {
+			self.hp -= damage;
+		}
+	}
+
+	/// Moves to the next phases once the HP of the current one is depleted or it timed out
+	fn update_phase(&mut self, clock: &SimClock) {
+		while self.phase + 1 < self.def.phases.len() {
+			let phase = &self.def.phases[self.phase];
+			let timed_out = phase
+				.timeout
+				.is_some_and(|t| clock.since(self.phase_begin) >= duration_to_ticks(t));
+			if self.hp > self.def.threshold(self.phase) && !timed_out {
+				break;
+			}
+			self.phase += 1;
+			self.phase_begin = clock.now();
+			self.fire_cd = Cooldown::with_duration(self.def.phases[self.phase].fire_interval);
+			log::debug!("Boss {} enters phase {}", self.def.name, self.phase);
+		}
+	}
+
+	/// Moves the boss and returns the bullets it shoots
+	pub fn update(
+		&mut self,
+		bounds: RectF,
+		player_pos: Point2<f32>,
+		clock: &SimClock,
+		dt: f32,
+	) -> Vec<Projectile> {
+		const SPEED: f32 = 1.5;
+		if !self.entered {
+			self.pos.y += SPEED * dt / DT_60;
+			if self.pos.y >= BOSS_HOME_Y {
+				self.entered = true;
+				self.phase_begin = clock.now();
+				self.vel = Vector2::unit_x() * SPEED;
+			}
+			return vec![];
+		}
+
+		self.update_phase(clock);
+		let phase = &self.def.phases[self.phase];
+		match phase.movement {
+			BossMovement::Hover => self.vel = Vector2::zero(),
+			BossMovement::Sweep => {
+				let (left, right) = (self.size.w / 2., bounds.dims.w - self.size.w / 2.);
+				if self.vel.x == 0. || (self.pos.x <= left && self.vel.x < 0.) {
+					self.vel = Vector2::unit_x() * SPEED;
+				} else if self.pos.x >= right && self.vel.x > 0. {
+					self.vel = Vector2::unit_x() * -SPEED;
+				}
+			},
+			BossMovement::Chase => {
+				self.vel = Vector2::unit_x() * (player_pos.x - self.pos.x).clamp(-SPEED, SPEED);
+			},
+		}
+		self.pos += self.vel * dt / DT_60;
+
+		if !self.fire_cd.is_over(clock) {
+			return vec![];
+		}
+		self.fire_cd.reset(clock);
+		const BULLET_SPEED: f32 = 5.;
+		let pos = self.pos + self.size.h * 0.5 * Vector2::unit_y();
+		let variant = phase.pattern.proj_type();
+		phase
+			.pattern
+			.directions(pos, player_pos)
+			.into_iter()
+			.map(|dir| Projectile::new(pos, dir * BULLET_SPEED, variant))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::clock::TICKS_PER_SEC;
+
+	/// Boss with phases of this HP and timeout in seconds, already at its place
+	fn boss(phases: &[(f32, Option<u64>)], clock: &SimClock) -> Boss {
+		let phases = phases
+			.iter()
+			.map(|&(hp, timeout)| BossPhase {
+				hp,
+				timeout: timeout.map(Duration::from_secs),
+				movement: BossMovement::Hover,
+				pattern: ShotPattern::Down,
+				fire_interval: Duration::from_secs(1),
+			})
+			.collect();
+		let def = BossDef { name: "test".into(), hitbox: None, phases };
+		let mut boss = Boss::spawn(Point2::new(200., BOSS_HOME_Y), def, 0, clock);
+		let bounds = RectF { top_left: (0., 0.).into(), dims: (400., 600.).into() };
+		boss.update(bounds, Point2::new(200., 500.), clock, DT_60);
+		assert!(boss.entered);
+		boss
+	}
+
+	#[test]
+	fn shots_miss_before_entering() {
+		let clock = SimClock::new();
+		let def = boss(&[(10., None)], &clock).def;
+		let mut boss = Boss::spawn(Point2::new(200., -50.), def, 0, &clock);
+		boss.get_shot(5.);
+		assert_eq!(boss.hp, 10.);
+		boss.entered = true;
+		boss.get_shot(5.);
+		assert_eq!(boss.hp, 5.);
+	}
+
+	#[test]
+	fn phases_end_at_their_hp() {
+		let clock = SimClock::new();
+		let mut boss = boss(&[(10., None), (20., None), (30., None)], &clock);
+		assert_eq!(
+			(boss.hp, boss.def.threshold(0), boss.def.threshold(1)),
+			(60., 50., 30.)
+		);
+		boss.get_shot(9.);
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 0);
+		boss.get_shot(1.);
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 1);
+		// Depleting the HP of several phases at once goes through all of them
+		boss.get_shot(25.);
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 2);
+		// The last phase lasts until the boss dies
+		boss.get_shot(25.);
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 2);
+		assert!(boss.is_dead());
+	}
+
+	#[test]
+	fn phases_time_out() {
+		let mut clock = SimClock::new();
+		let mut boss = boss(&[(10., Some(1)), (20., Some(2)), (30., None)], &clock);
+		for _ in 1..TICKS_PER_SEC {
+			clock.advance();
+		}
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 0);
+		clock.advance();
+		boss.update_phase(&clock);
+		assert_eq!((boss.phase, boss.phase_begin), (1, clock.now()));
+		// The timeout of the new phase counts from its beginning, the HP is kept
+		for _ in 0..2 * TICKS_PER_SEC - 1 {
+			clock.advance();
+		}
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 1);
+		clock.advance();
+		boss.update_phase(&clock);
+		assert_eq!((boss.phase, boss.hp), (2, 60.));
+	}
+
+	#[test]
+	fn timeout_and_hp_end_phases_in_one_tick() {
+		let mut clock = SimClock::new();
+		let mut boss = boss(&[(10., Some(1)), (20., None), (30., None)], &clock);
+		for _ in 0..TICKS_PER_SEC {
+			clock.advance();
+		}
+		// The first phase timed out and the HP of the second one is depleted
+		boss.get_shot(30.);
+		boss.update_phase(&clock);
+		assert_eq!(boss.phase, 2);
+	}
+}
@@ -0,0 +1,199 @@
+use cgmath::{Point2, Vector2};
+
+use crate::{
+	coords::Dimensions,
+	game::Inputs,
+	gameplay::{ProjType, World, PLAYER_SHOT_SPEED, PLAYER_SPEED},
+};
+
+/// Ticks looked ahead for each move
+const HORIZON: u32 = 18;
+/// Distance kept between the hitbox and the bullets, in pixels
+const MARGIN: f32 = 6.;
+/// Height of the play area where the bot waits, as a share of the height
+const HOME_Y: f32 = 0.8;
+
+/// Player controlled by the game, for automated playtesting
+///
+/// Each tick, the bot tries every direction, held for the next ticks, against the bullets moving in
+/// straight lines, and takes the safest one, preferring to stand below an enemy. It always shoots
+/// while enemies are alive.
+#[derive(Clone, Debug, Default)]
+pub struct Autopilot {
+	/// Direction taken at the previous tick, kept when the others aren't better
+	last_move: (i8, i8),
+}
+
+impl Autopilot {
+	pub fn new() -> Autopilot {
+		Autopilot::default()
+	}
+
+	pub fn inputs(&mut self, world: &World) -> Inputs {
+		let player = &world.player;
+		let bounds = world.boundaries;
+		// Enemies with their width, where they will be when the shots reach them
+		let lead = |pos: Point2<f32>, prev_pos: Point2<f32>| {
+			let flight = ((player.pos.y - pos.y) / PLAYER_SHOT_SPEED).max(0.);
+			pos + (pos - prev_pos) * flight
+		};
+		// Closest enemy horizontally, the others are further to reach
+		let target = world
+			.enemies
+			.iter()
+			.filter(|enemy| bounds.contains(enemy.pos))
+			.map(|enemy| (lead(enemy.pos, enemy.prev_pos), enemy.size.w))
+			.chain(
+				world
+					.bosses
+					.iter()
+					.map(|boss| (lead(boss.pos, boss.prev_pos), boss.size.w)),
+			)
+			.min_by(|(a, _), (b, _)| {
+				(a.x - player.pos.x)
+					.abs()
+					.total_cmp(&(b.x - player.pos.x).abs())
+			});
+		let hitbox = player.hitbox.extents();
+		// Gaps on each axis under which a bullet of this size hits the player
+		let reach = |bullet: Dimensions<f32>| {
+			Vector2::new(
+				(hitbox.w + bullet.w) / 2. + MARGIN,
+				(hitbox.h + bullet.h) / 2. + MARGIN,
+			)
+		};
+		// Shots hit the target from anywhere under it, but its bullets going down come from its
+		// middle, so the bot waits on the side of the target closest to the player
+		let home_x = match target {
+			Some((pos, width)) => {
+				let shot = ProjType::PlayerShoot.hitbox().extents();
+				let down = reach(ProjType::Basic.hitbox().extents());
+				let offset = ((width + shot.w) / 2. + down.x) / 2.;
+				let side = if player.pos.x < pos.x { -1. } else { 1. };
+				(pos.x + side * offset).clamp(0., bounds.dims.w)
+			},
+			None => bounds.dims.w / 2.,
+		};
+		let home = Point2::new(home_x, bounds.dims.h * HOME_Y);
+		let projectiles = &world.projectiles;
+		let bullets: Vec<(Point2<f32>, Vector2<f32>, Vector2<f32>)> = projectiles
+			.slots()
+			.filter(|&slot| !matches!(projectiles.variant[slot], ProjType::PlayerShoot))
+			.map(|slot| {
+				let half = reach(projectiles.variant[slot].hitbox().extents());
+				(projectiles.pos[slot], projectiles.vel[slot], half)
+			})
+			.collect();
+
+		let mut best = (f32::INFINITY, (0, 0));
+		for dx in -1..=1 {
+			for dy in -1..=1 {
+				let mut pos = player.pos;
+				let mut danger = 0.;
+				for t in 1..=HORIZON {
+					// Same movement as the player, stopped on each axis by the edges
+					let next = pos + Vector2::new(dx as f32, dy as f32) * PLAYER_SPEED;
+					if 0. <= next.x && next.x <= bounds.dims.w {
+						pos.x = next.x;
+					}
+					if 0. <= next.y && next.y <= bounds.dims.h {
+						pos.y = next.y;
+					}
+					for (bullet_pos, vel, half) in &bullets {
+						let bullet = bullet_pos + vel * t as f32;
+						let (gap_x, gap_y) = ((bullet.x - pos.x).abs(), (bullet.y - pos.y).abs());
+						if gap_x < half.x && gap_y < half.y {
+							// Close hits matter more, the bot can still avoid the later ones
+							danger += (HORIZON + 1 - t) as f32 * 100.;
+						} else if gap_x < 2. * half.x && gap_y < 2. * half.y {
+							danger += 0.5;
+						}
+					}
+				}
+				let to_home = home - pos;
+				let mut cost = danger + to_home.x.abs() * 0.3 + to_home.y.abs() * 0.05;
+				if (dx, dy) != self.last_move {
+					// Avoids shaking between two moves of similar cost
+					cost += 0.5;
+				}
+				if cost < best.0 {
+					best = (cost, (dx, dy));
+				}
+			}
+		}
+
+		let (dx, dy) = best.1;
+		self.last_move = (dx, dy);
+		Inputs {
+			left: dx < 0,
+			right: dx > 0,
+			up: dy < 0,
+			down: dy > 0,
+			shoot: target.is_some(),
+			..Default::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{path::Path, rc::Rc};
+
+	use super::*;
+	use crate::{
+		clock::TICKS_PER_SEC,
+		enemy::{EnemyTypes, ENEMY_DIR},
+		game::WORLD_SIZE,
+		level::Level,
+		projectiles::Projectile,
+	};
+
+	fn start(content: &str) -> World {
+		let enemy_types = Rc::new(EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap());
+		let level = Level::parse_hbh(Path::new("levels/test.hbh"), content, 0, &enemy_types).unwrap();
+		World::start(WORLD_SIZE, &level, enemy_types)
+	}
+
+	/// Hits taken by the player under the bot, or standing still when `bot` is `None`
+	fn hits(world: &mut World, mut bot: Option<Autopilot>, ticks: u32) -> u32 {
+		for _ in 0..ticks {
+			let inputs = bot
+				.as_mut()
+				.map_or(Inputs::default(), |bot| bot.inputs(world));
+			world.tick(&inputs);
+		}
+		world.player_hits
+	}
+
+	#[test]
+	fn bullets_are_dodged() {
+		for variant in [ProjType::Basic, ProjType::Aimed] {
+			// A late enemy keeps the level going
+			let mut world = start("@spawn-enemy basic 60 200 -25 -");
+			let pos = world.player.pos - Vector2::new(0., 60.);
+			let bullet = Projectile::new(pos, Vector2::new(0., 4.), variant);
+			world.projectiles.push(bullet.clone());
+			let mut still = world.clone();
+			assert_eq!(hits(&mut still, None, TICKS_PER_SEC), 1);
+			assert_eq!(hits(&mut world, Some(Autopilot::new()), TICKS_PER_SEC), 0);
+		}
+	}
+
+	#[test]
+	fn enemies_are_aimed_at() {
+		let mut world = start("@spawn-enemy basic 0 300 -25 -");
+		let mut bot = Autopilot::new();
+		for _ in 0..5 * TICKS_PER_SEC {
+			let inputs = bot.inputs(&world);
+			// Enemies are targeted once they enter the play area
+			let visible = world
+				.enemies
+				.iter()
+				.any(|enemy| world.boundaries.contains(enemy.pos));
+			assert_eq!(inputs.shoot, visible);
+			world.tick(&inputs);
+		}
+		assert!(world.shots_hit > 0);
+		assert_eq!(world.player_hits, 0);
+	}
+}
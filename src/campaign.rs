@@ -0,0 +1,403 @@
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	gameplay::LevelEnd,
+	level::{Level, LevelParseError, LineTokens, ParseErrorKind},
+};
+
+/// Condition to unlock a level of the campaign
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnlockCondition {
+	/// The level with this id has been cleared
+	Clear(u32),
+	/// The best scores of all the cleared levels add up to at least this
+	Score(u64),
+	/// The level to clear, with this file name, isn't loaded, the level stays locked
+	Missing(String),
+}
+
+/// Condition of the manifest, before the levels are loaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestCondition {
+	/// File name of the level to clear, positions are replaced by the file at this position
+	Clear(String),
+	Score(u64),
+}
+
+#[derive(Clone, Debug)]
+pub struct CampaignEntry {
+	/// File name of the level, in the levels directory
+	pub file: String,
+	pub unlock: Vec<ManifestCondition>,
+}
+
+/// Ordered list of the levels, read from `campaign.hbc`
+#[derive(Clone, Debug)]
+pub struct Campaign {
+	pub entries: Vec<CampaignEntry>,
+}
+
+impl Campaign {
+	pub fn parse(manifest: &Path) -> Result<Campaign, LevelParseError> {
+		let raw_data = fs::read_to_string(manifest).map_err(|err| LevelParseError {
+			file: manifest.to_path_buf(),
+			line: 0,
+			column: 0,
+			kind: ParseErrorKind::Io(err.to_string()),
+		})?;
+		Campaign::parse_manifest(manifest, &raw_data)
+	}
+
+	/// Parses the content of the manifest, `manifest` is only used in the errors
+	pub fn parse_manifest(manifest: &Path, raw_data: &str) -> Result<Campaign, LevelParseError> {
+		let mut entries = vec![];
+		// Levels to clear with their line and column, checked once every entry is known
+		let mut clears = vec![];
+		for (i, line) in raw_data.lines().enumerate() {
+			let content = line.trim_start();
+			let Some(entry) = content.strip_prefix('@') else {
+				continue;
+			};
+			let offset = line.chars().count() - content.chars().count() + 1;
+			let mut tokens = LineTokens::new(manifest, i + 1, entry, offset);
+			let (col, key) = tokens.next("campaign entry (level)")?;
+			if key != "level" {
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken {
+						expected: "campaign entry (level)",
+						found: key.into(),
+					},
+				));
+			}
+			let (_, file) = tokens.next("level file name")?;
+			let mut unlock = vec![];
+			while let Ok((col, condition)) = tokens.next("unlock condition") {
+				match condition {
+					"clear" => {
+						let (col, level) = tokens.next("level to clear (position or file name)")?;
+						clears.push((entries.len(), unlock.len(), i + 1, col, level));
+						unlock.push(ManifestCondition::Clear(level.into()));
+					},
+					"score" => unlock.push(ManifestCondition::Score(tokens.parse("total score")?)),
+					condition => {
+						return Err(tokens.error(
+							col,
+							ParseErrorKind::InvalidToken {
+								expected: "unlock condition (clear, score)",
+								found: condition.into(),
+							},
+						))
+					},
+				}
+			}
+			entries.push(CampaignEntry { file: file.into(), unlock });
+		}
+		for (entry, condition, line, column, level) in clears {
+			let file = match level.parse::<usize>() {
+				Ok(position) => position
+					.checked_sub(1)
+					.and_then(|index| entries.get(index))
+					.map(|entry| entry.file.clone()),
+				Err(_) => entries
+					.iter()
+					.any(|entry| entry.file == level)
+					.then(|| level.to_string()),
+			};
+			let Some(file) = file else {
+				return Err(LevelParseError {
+					file: manifest.to_path_buf(),
+					line,
+					column,
+					kind: ParseErrorKind::InvalidToken {
+						expected: "level of the campaign (position from 1 or file name)",
+						found: level.into(),
+					},
+				});
+			};
+			entries[entry].unlock[condition] = ManifestCondition::Clear(file);
+		}
+		Ok(Campaign { entries })
+	}
+
+	/// Puts the levels in the order of the manifest with their unlock conditions, unlisted levels
+	/// are kept at the end without conditions
+	///
+	/// The levels of the manifest that aren't loaded keep the levels needing them locked.
+	pub fn order(&self, levels: &mut Vec<Level>) {
+		let mut unlisted = std::mem::take(levels);
+		let mut conditions = vec![];
+		for entry in &self.entries {
+			match unlisted.iter().position(|level| level.file == entry.file) {
+				Some(index) => {
+					levels.push(unlisted.remove(index));
+					conditions.push(&entry.unlock);
+				},
+				None => log::warn!("Level '{}' of the campaign isn't loaded", entry.file),
+			}
+		}
+		for (level_idx, unlock) in conditions.into_iter().enumerate() {
+			let resolved = unlock
+				.iter()
+				.map(|condition| match condition {
+					ManifestCondition::Score(score) => UnlockCondition::Score(*score),
+					ManifestCondition::Clear(file) => {
+						match levels.iter().position(|level| &level.file == file) {
+							Some(id) => UnlockCondition::Clear(id as u32),
+							None => UnlockCondition::Missing(file.clone()),
+						}
+					},
+				})
+				.collect();
+			levels[level_idx].unlock = resolved;
+		}
+		levels.append(&mut unlisted);
+	}
+}
+
+/// Levels cleared by the player with their best score, saved between sessions
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+	/// Best score by level file name
+	best_scores: HashMap<String, u64>,
+}
+
+pub const SAVE_FILE: &str = "./save.hbs";
+
+impl Progress {
+	/// Reads the save file, a missing file is an empty progress
+	pub fn load(path: &Path) -> Progress {
+		let mut progress = Progress::default();
+		let Ok(raw_data) = fs::read_to_string(path) else {
+			return progress;
+		};
+		for line in raw_data.lines() {
+			match line
+				.rsplit_once(' ')
+				.map(|(file, score)| (file, score.parse()))
+			{
+				Some((file, Ok(score))) => {
+					progress.best_scores.insert(file.into(), score);
+				},
+				_ => log::warn!("Ignoring invalid line '{line}' in {}", path.display()),
+			}
+		}
+		progress
+	}
+
+	pub fn save(&self, path: &Path) {
+		let mut lines: Vec<String> = self
+			.best_scores
+			.iter()
+			.map(|(file, score)| format!("{file} {score}"))
+			.collect();
+		lines.sort();
+		if let Err(err) = fs::write(path, lines.join("\n") + "\n") {
+			log::error!("Can't write save file {}: {err}", path.display());
+		}
+	}
+
+	pub fn record_clear(&mut self, file: &str, score: u64) {
+		let best = self.best_scores.entry(file.into()).or_default();
+		*best = score.max(*best);
+	}
+
+	/// Records the end of a run, returns whether the level was cleared with a new best score
+	///
+	/// Watching a replay doesn't change the progress.
+	pub fn record_run(&mut self, file: &str, end: LevelEnd, score: u64, replay: bool) -> bool {
+		if end != LevelEnd::Cleared || replay {
+			return false;
+		}
+		let best = self.best_score(file).is_none_or(|best| score > best);
+		self.record_clear(file, score);
+		best
+	}
+
+	pub fn best_score(&self, file: &str) -> Option<u64> {
+		self.best_scores.get(file).copied()
+	}
+
+	pub fn is_cleared(&self, file: &str) -> bool {
+		self.best_scores.contains_key(file)
+	}
+
+	pub fn total_score(&self) -> u64 {
+		self.best_scores.values().sum()
+	}
+
+	/// Whether every unlock condition of the level is met, `levels` are indexed by id
+	pub fn unlocks(&self, level: &Level, levels: &[Level]) -> bool {
+		level.unlock.iter().all(|condition| match condition {
+			UnlockCondition::Clear(id) => levels
+				.get(*id as usize)
+				.is_some_and(|cleared| self.is_cleared(&cleared.file)),
+			UnlockCondition::Score(score) => self.total_score() >= *score,
+			UnlockCondition::Missing(_) => false,
+		})
+	}
+}
+
+/// Path of the manifest in the levels directory
+pub fn manifest_path(level_dir: &Path) -> PathBuf {
+	level_dir.join("campaign.hbc")
+}
+
+#[cfg(test)]
+mod tests {
+	use std::rc::Rc;
+
+	use super::*;
+
+	const MANIFEST: &str = "# Levels\n\
+		@level a.hbh\n\
+		@level b.hbh clear 1\n\
+		@level c.hbh clear b.hbh score 1000\n";
+
+	fn parse(content: &str) -> Result<Campaign, LevelParseError> {
+		Campaign::parse_manifest(Path::new("levels/campaign.hbc"), content)
+	}
+
+	fn level(file: &str) -> Level {
+		Level {
+			id: 0,
+			file: file.into(),
+			name: Rc::new(file.into()),
+			meta: Default::default(),
+			unlock: vec![],
+			event_list: vec![],
+		}
+	}
+
+	fn files(levels: &[Level]) -> Vec<&str> {
+		levels.iter().map(|level| level.file.as_str()).collect()
+	}
+
+	#[test]
+	fn manifest_is_parsed() {
+		let campaign = parse(MANIFEST).unwrap();
+		let unlocks: Vec<(&str, &[ManifestCondition])> = campaign
+			.entries
+			.iter()
+			.map(|entry| (entry.file.as_str(), &entry.unlock[..]))
+			.collect();
+		assert_eq!(
+			unlocks,
+			[
+				("a.hbh", &[][..]),
+				("b.hbh", &[ManifestCondition::Clear("a.hbh".into())][..]),
+				(
+					"c.hbh",
+					&[
+						ManifestCondition::Clear("b.hbh".into()),
+						ManifestCondition::Score(1000)
+					][..]
+				),
+			]
+		);
+		// Positions may refer to the levels listed after
+		let campaign = parse("@level a.hbh clear 2\n@level b.hbh").unwrap();
+		assert_eq!(
+			campaign.entries[0].unlock,
+			[ManifestCondition::Clear("b.hbh".into())]
+		);
+	}
+
+	#[test]
+	fn invalid_manifests_are_located() {
+		for (content, line, column, found) in [
+			("@level a.hbh\n@level b.hbh clear 0", 2, 20, "0"),
+			("@level a.hbh\n@level b.hbh clear 3", 2, 20, "3"),
+			(
+				"@level a.hbh\n@level b.hbh clear other.hbh",
+				2,
+				20,
+				"other.hbh",
+			),
+			("@level a.hbh unlock 1", 1, 14, "unlock"),
+			("@level a.hbh score lots", 1, 20, "lots"),
+			("@stage a.hbh", 1, 2, "stage"),
+		] {
+			let err = parse(content).unwrap_err();
+			assert_eq!((err.line, err.column), (line, column), "{content}");
+			match err.kind {
+				ParseErrorKind::InvalidToken { found: token, .. } => assert_eq!(token, found),
+				kind => panic!("{content}: {kind:?}"),
+			}
+		}
+		let err = parse("@level").unwrap_err();
+		assert!(matches!(err.kind, ParseErrorKind::MissingToken { .. }));
+	}
+
+	#[test]
+	fn levels_are_ordered_and_unlocked() {
+		let campaign = parse(MANIFEST).unwrap();
+		let mut levels = vec![
+			level("extra.hbh"),
+			level("c.hbh"),
+			level("a.hbh"),
+			level("b.hbh"),
+		];
+		campaign.order(&mut levels);
+		assert_eq!(files(&levels), ["a.hbh", "b.hbh", "c.hbh", "extra.hbh"]);
+		assert_eq!(
+			levels[2].unlock,
+			[UnlockCondition::Clear(1), UnlockCondition::Score(1000)]
+		);
+		assert!(levels[3].unlock.is_empty());
+
+		let mut progress = Progress::default();
+		let unlocked = |progress: &Progress| {
+			levels
+				.iter()
+				.map(|level| progress.unlocks(level, &levels))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(unlocked(&progress), [true, false, false, true]);
+		progress.record_clear("a.hbh", 300);
+		progress.record_clear("b.hbh", 500);
+		assert_eq!(unlocked(&progress), [true, true, false, true]);
+		progress.record_clear("a.hbh", 600);
+		progress.record_clear("a.hbh", 100);
+		assert_eq!(
+			(progress.best_score("a.hbh"), progress.total_score()),
+			(Some(600), 1100)
+		);
+		assert_eq!(unlocked(&progress), [true, true, true, true]);
+	}
+
+	#[test]
+	fn replays_dont_change_the_progress() {
+		let mut progress = Progress::default();
+		assert!(!progress.record_run("a.hbh", LevelEnd::Cleared, 500, true));
+		assert!(!progress.record_run("a.hbh", LevelEnd::Died, 500, false));
+		assert!(!progress.is_cleared("a.hbh"));
+		assert!(progress.record_run("a.hbh", LevelEnd::Cleared, 500, false));
+		assert!(!progress.record_run("a.hbh", LevelEnd::Cleared, 400, false));
+		assert!(!progress.record_run("a.hbh", LevelEnd::Cleared, 900, true));
+		assert_eq!(progress.best_score("a.hbh"), Some(500));
+	}
+
+	#[test]
+	fn missing_levels_keep_their_dependents_locked() {
+		let campaign = parse(MANIFEST).unwrap();
+		// b.hbh failed to load
+		let mut levels = vec![level("a.hbh"), level("c.hbh")];
+		campaign.order(&mut levels);
+		assert_eq!(files(&levels), ["a.hbh", "c.hbh"]);
+		assert_eq!(
+			levels[1].unlock,
+			[
+				UnlockCondition::Missing("b.hbh".into()),
+				UnlockCondition::Score(1000)
+			]
+		);
+		let mut progress = Progress::default();
+		progress.record_clear("a.hbh", 5000);
+		assert!(!progress.unlocks(&levels[1], &levels));
+	}
+}
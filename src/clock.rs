@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+/// The simulation advances in ticks of fixed duration, whatever the frame rate
+pub const TICKS_PER_SEC: u32 = 60;
+pub const TICK: Duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SEC as u64);
+/// Longest frame time simulated, the game slows down instead of freezing on longer frames
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Number of ticks in a duration, rounded to the nearest
+pub fn duration_to_ticks(t: Duration) -> u64 {
+	(t.as_secs_f64() * TICKS_PER_SEC as f64).round() as u64
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+	Duration::from_secs_f64(ticks as f64 / TICKS_PER_SEC as f64)
+}
+
+/// Time of the simulation, counted in ticks, which every timer of the world reads
+///
+/// The clock only moves with `advance`, so it stops while the game is paused and doesn't drift
+/// with the frame rate.
+#[derive(Clone, Debug)]
+pub struct SimClock {
+	/// Ticks simulated since the beginning of the level
+	tick: u64,
+	paused: bool,
+	/// Real time not simulated yet, less than a tick after `frame`
+	accumulator: Duration,
+}
+
+impl Default for SimClock {
+	fn default() -> Self {
+		SimClock::new()
+	}
+}
+
+impl SimClock {
+	pub fn new() -> SimClock {
+		SimClock { tick: 0, paused: false, accumulator: Duration::ZERO }
+	}
+
+	/// Current tick
+	pub fn now(&self) -> u64 {
+		self.tick
+	}
+
+	/// Simulated time since the beginning
+	pub fn elapsed(&self) -> Duration {
+		ticks_to_duration(self.tick)
+	}
+
+	/// Ticks since `tick`
+	pub fn since(&self, tick: u64) -> u64 {
+		self.tick.saturating_sub(tick)
+	}
+
+	/// Moves to the next tick
+	pub fn advance(&mut self) {
+		self.tick += 1;
+	}
+
+	/// Adds the real time of a frame and returns the number of ticks to simulate for it
+	pub fn frame(&mut self, dt: Duration) -> u32 {
+		if self.paused {
+			return 0;
+		}
+		self.accumulator += dt.min(MAX_FRAME_TIME);
+		let ticks = (self.accumulator.as_nanos() / TICK.as_nanos()) as u32;
+		self.accumulator -= TICK * ticks;
+		ticks
+	}
+
+	/// Progress between the previous tick and the next one, for drawing
+	pub fn alpha(&self) -> f32 {
+		self.accumulator.as_secs_f32() / TICK.as_secs_f32()
+	}
+
+	pub fn pause(&mut self) {
+		self.paused = true;
+	}
+
+	pub fn resume(&mut self) {
+		self.paused = false;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frames_to_ticks() {
+		let mut clock = SimClock::new();
+		// Two frames of 2/3 tick make a tick, and a third of a tick is left
+		assert_eq!(clock.frame(TICK * 2 / 3), 0);
+		assert!((clock.alpha() - 2. / 3.).abs() < 1e-3);
+		assert_eq!(clock.frame(TICK * 2 / 3), 1);
+		assert!((clock.alpha() - 1. / 3.).abs() < 1e-3);
+		assert_eq!(clock.frame(TICK * 3), 3);
+		// `frame` only counts the ticks, the world advances the clock
+		assert_eq!(clock.now(), 0);
+		clock.advance();
+		assert_eq!((clock.now(), clock.since(0), clock.since(5)), (1, 1, 0));
+	}
+
+	#[test]
+	fn long_frames_are_clamped() {
+		let mut clock = SimClock::new();
+		let max_ticks = clock.frame(MAX_FRAME_TIME);
+		assert_eq!(clock.frame(Duration::from_secs(10)), max_ticks);
+		assert_eq!(max_ticks as u64, duration_to_ticks(MAX_FRAME_TIME));
+	}
+
+	#[test]
+	fn pause_and_resume() {
+		let mut clock = SimClock::new();
+		clock.frame(TICK / 2);
+		clock.pause();
+		assert_eq!(clock.frame(TICK * 10), 0);
+		clock.resume();
+		// The time of the frames while paused is lost, not the time before the pause
+		assert_eq!(clock.frame(TICK / 2), 1);
+	}
+
+	#[test]
+	fn ticks_and_durations() {
+		assert_eq!(duration_to_ticks(Duration::from_secs(2)), 120);
+		assert_eq!(duration_to_ticks(Duration::from_millis(1010)), 61);
+		assert_eq!(ticks_to_duration(90), Duration::from_millis(1500));
+		let mut clock = SimClock::new();
+		(0..30).for_each(|_| clock.advance());
+		assert_eq!(clock.elapsed(), Duration::from_millis(500));
+	}
+}
@@ -1,5 +1,6 @@
-use cgmath::Point2;
+use cgmath::{InnerSpace, Point2, Vector2};
 use num::{NumCast, Zero};
+use serde::{Deserialize, Serialize};
 use std::{
 	cmp::PartialOrd,
 	convert::{From, Into},
@@ -8,7 +9,7 @@ use std::{
 };
 use winit::dpi::PhysicalSize;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Dimensions<T: Copy> {
 	pub w: T,
 	pub h: T,
@@ -28,9 +29,15 @@ impl<T: Copy + NumCast> Dimensions<T> {
 
 /// Implements a given operation for Dimensions
 ///
-/// Basic usage:
+/// Basic usage, `impl_operation!(Add, add, +, u32, u32)` adds a scalar, a tuple or other
+/// Dimensions to both sides:
 /// ```rust
-/// impl_operation!(Add, add, +, u32, u32);
+/// use holy_bullet_hell::coords::Dimensions;
+///
+/// let dims = Dimensions { w: 4_u32, h: 6 };
+/// let sums = [dims + 1, dims + (1, 2), dims + Dimensions { w: 1, h: 2 }];
+/// let sums: Vec<(u32, u32)> = sums.iter().map(|sum| (sum.w, sum.h)).collect();
+/// assert_eq!(sums, [(5, 7), (5, 8), (5, 8)]);
 /// ```
 macro_rules! impl_operation {
 	($trait: ident, $fun: ident, $symb: tt, $rhs_t: ty, $lhs_t: ty) => {
@@ -312,3 +319,274 @@ pub fn collide_rectangle(
 		|| pos_a.y + size_a.h / 2. < pos_b.y - size_b.h / 2.
 		|| pos_a.y - size_a.h / 2. > pos_b.y + size_b.h / 2.)
 }
+
+/// Hitbox of an entity, centered on its position
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Shape {
+	Circle {
+		radius: f32,
+	},
+	/// Axis-aligned box
+	Aabb(Dimensions<f32>),
+	/// Segment from `-half` to `half` around the position, widened by `radius`, for lasers
+	Capsule {
+		half: Vector2<f32>,
+		radius: f32,
+	},
+}
+
+impl Shape {
+	/// Size of the smallest box containing the shape
+	pub fn extents(&self) -> Dimensions<f32> {
+		match *self {
+			Shape::Circle { radius } => Dimensions { w: radius * 2., h: radius * 2. },
+			Shape::Aabb(dims) => dims,
+			Shape::Capsule { half, radius } => {
+				Dimensions { w: (half.x.abs() + radius) * 2., h: (half.y.abs() + radius) * 2. }
+			},
+		}
+	}
+
+	/// Whether the sizes and the radius are finite and greater than 0, a capsule may have a length
+	/// of 0
+	pub fn is_valid(&self) -> bool {
+		let positive = |value: f32| value.is_finite() && value > 0.;
+		match *self {
+			Shape::Circle { radius } => positive(radius),
+			Shape::Aabb(dims) => positive(dims.w) && positive(dims.h),
+			Shape::Capsule { half, radius } => {
+				half.x.is_finite() && half.y.is_finite() && positive(radius)
+			},
+		}
+	}
+
+	/// Segment and radius of the round shapes, a circle is a capsule of length 0
+	fn round(&self, pos: Point2<f32>) -> Option<(Point2<f32>, Point2<f32>, f32)> {
+		match *self {
+			Shape::Circle { radius } => Some((pos, pos, radius)),
+			Shape::Aabb(_) => None,
+			Shape::Capsule { half, radius } => Some((pos - half, pos + half, radius)),
+		}
+	}
+
+	/// Whether the shape at `pos` touches the other shape at `other_pos`
+	pub fn intersects(&self, pos: Point2<f32>, other: &Shape, other_pos: Point2<f32>) -> bool {
+		match (self.round(pos), other.round(other_pos)) {
+			(Some((a, b, r_a)), Some((c, d, r_b))) => {
+				segments_distance2(a, b, c, d) <= (r_a + r_b) * (r_a + r_b)
+			},
+			(Some((a, b, radius)), None) => {
+				segment_box_distance2(a, b, other_pos, other.extents()) <= radius * radius
+			},
+			(None, Some((a, b, radius))) => {
+				segment_box_distance2(a, b, pos, self.extents()) <= radius * radius
+			},
+			(None, None) => collide_rectangle(pos, self.extents(), other_pos, other.extents()),
+		}
+	}
+}
+
+/// Square of the distance between `p` and the segment from `a` to `b`
+fn point_segment_distance2(p: Point2<f32>, a: Point2<f32>, b: Point2<f32>) -> f32 {
+	let ab = b - a;
+	let length2 = ab.magnitude2();
+	let t = if length2 == 0. {
+		0.
+	} else {
+		((p - a).dot(ab) / length2).clamp(0., 1.)
+	};
+	(a + ab * t - p).magnitude2()
+}
+
+fn cross(u: Vector2<f32>, v: Vector2<f32>) -> f32 {
+	u.x * v.y - u.y * v.x
+}
+
+/// Square of the distance between two segments, 0 if they cross
+fn segments_distance2(a: Point2<f32>, b: Point2<f32>, c: Point2<f32>, d: Point2<f32>) -> f32 {
+	let crossing = cross(d - c, a - c) * cross(d - c, b - c) < 0.
+		&& cross(b - a, c - a) * cross(b - a, d - a) < 0.;
+	if crossing {
+		return 0.;
+	}
+	// Otherwise the closest points include an end of one of the segments
+	point_segment_distance2(a, c, d)
+		.min(point_segment_distance2(b, c, d))
+		.min(point_segment_distance2(c, a, b))
+		.min(point_segment_distance2(d, a, b))
+}
+
+/// Whether the segment from `a` to `b` goes through the box, by clipping it on each axis
+fn segment_crosses_box(
+	a: Point2<f32>,
+	b: Point2<f32>,
+	center: Point2<f32>,
+	dims: Dimensions<f32>,
+) -> bool {
+	let (mut enter, mut exit) = (0_f32, 1_f32);
+	for (start, delta, half, mid) in [
+		(a.x, b.x - a.x, dims.w / 2., center.x),
+		(a.y, b.y - a.y, dims.h / 2., center.y),
+	] {
+		let (min, max) = (mid - half, mid + half);
+		if delta == 0. {
+			if start < min || start > max {
+				return false;
+			}
+			continue;
+		}
+		let (t_min, t_max) = ((min - start) / delta, (max - start) / delta);
+		enter = enter.max(t_min.min(t_max));
+		exit = exit.min(t_min.max(t_max));
+		if enter > exit {
+			return false;
+		}
+	}
+	true
+}
+
+/// Square of the distance between the segment from `a` to `b` and a box, 0 if they overlap
+fn segment_box_distance2(
+	a: Point2<f32>,
+	b: Point2<f32>,
+	center: Point2<f32>,
+	dims: Dimensions<f32>,
+) -> f32 {
+	if segment_crosses_box(a, b, center, dims) {
+		return 0.;
+	}
+	let point_box_distance2 = |p: Point2<f32>| {
+		let dx = ((p.x - center.x).abs() - dims.w / 2.).max(0.);
+		let dy = ((p.y - center.y).abs() - dims.h / 2.).max(0.);
+		dx * dx + dy * dy
+	};
+	// Otherwise the closest points include an end of the segment or a corner of the box
+	let corners = [(-1., -1.), (-1., 1.), (1., -1.), (1., 1.)]
+		.map(|(x, y)| center + Vector2::new(x * dims.w / 2., y * dims.h / 2.));
+	corners
+		.into_iter()
+		.map(|corner| point_segment_distance2(corner, a, b))
+		.fold(point_box_distance2(a).min(point_box_distance2(b)), f32::min)
+}
+
+/// Point between `prev` and `pos`, `alpha` going from 0 to 1
+pub fn interpolate(prev: Point2<f32>, pos: Point2<f32>, alpha: f32) -> Point2<f32> {
+	prev + (pos - prev) * alpha
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn circle(radius: f32) -> Shape {
+		Shape::Circle { radius }
+	}
+
+	fn capsule(x: f32, y: f32, radius: f32) -> Shape {
+		Shape::Capsule { half: Vector2::new(x, y), radius }
+	}
+
+	fn aabb(w: f32, h: f32) -> Shape {
+		Shape::Aabb(Dimensions { w, h })
+	}
+
+	/// Tests both orders, the result must not depend on it
+	fn intersects(a: Shape, pos_a: (f32, f32), b: Shape, pos_b: (f32, f32)) -> bool {
+		let ab = a.intersects(pos_a.into(), &b, pos_b.into());
+		assert_eq!(ab, b.intersects(pos_b.into(), &a, pos_a.into()));
+		ab
+	}
+
+	#[test]
+	fn circles() {
+		assert!(intersects(circle(4.), (0., 0.), circle(3.), (7., 0.)));
+		assert!(!intersects(circle(4.), (0., 0.), circle(3.), (7.1, 0.)));
+		assert!(intersects(circle(4.), (0., 0.), circle(3.), (4., 4.)));
+		assert!(!intersects(circle(4.), (0., 0.), circle(3.), (5., 5.)));
+	}
+
+	#[test]
+	fn boxes() {
+		assert!(intersects(aabb(10., 4.), (0., 0.), aabb(2., 2.), (6., 3.)));
+		assert!(!intersects(
+			aabb(10., 4.),
+			(0., 0.),
+			aabb(2., 2.),
+			(6.1, 0.)
+		));
+		assert!(!intersects(
+			aabb(10., 4.),
+			(0., 0.),
+			aabb(2., 2.),
+			(0., 3.1)
+		));
+	}
+
+	#[test]
+	fn circle_and_box() {
+		// Beside an edge, then near a corner where the box is closer than its extents say
+		assert!(intersects(circle(3.), (8., 0.), aabb(10., 10.), (0., 0.)));
+		assert!(!intersects(circle(3.), (8.1, 0.), aabb(10., 10.), (0., 0.)));
+		assert!(intersects(circle(3.), (7., 7.), aabb(10., 10.), (0., 0.)));
+		assert!(!intersects(
+			circle(3.),
+			(7.2, 7.2),
+			aabb(10., 10.),
+			(0., 0.)
+		));
+		// Inside the box
+		assert!(intersects(circle(1.), (0., 0.), aabb(10., 10.), (0., 0.)));
+	}
+
+	#[test]
+	fn capsules() {
+		let shot = capsule(0., 2., 4.);
+		// The ends of the capsule reach further than its radius
+		assert!(intersects(shot, (0., 0.), circle(1.), (0., 7.)));
+		assert!(!intersects(shot, (0., 0.), circle(1.), (0., 7.1)));
+		assert!(!intersects(shot, (0., 0.), circle(1.), (5.1, 0.)));
+		// Crossing segments
+		assert!(intersects(
+			capsule(10., 0., 0.5),
+			(0., 0.),
+			capsule(0., 10., 0.5),
+			(0., 0.)
+		));
+		assert!(!intersects(
+			capsule(10., 0., 0.5),
+			(0., 0.),
+			capsule(0., 3., 0.5),
+			(0., 5.)
+		));
+		// Long capsule going through a box without its ends touching it
+		assert!(intersects(
+			capsule(50., 0., 1.),
+			(0., 0.),
+			aabb(4., 4.),
+			(0., 2.5)
+		));
+		assert!(!intersects(
+			capsule(50., 0., 1.),
+			(0., 0.),
+			aabb(4., 4.),
+			(0., 3.1)
+		));
+		// Diagonal capsule passing at 2√2 from a corner
+		let diagonal = |radius| capsule(10., -10., radius);
+		assert!(!intersects(diagonal(2.8), (0., 0.), aabb(4., 4.), (4., 4.)));
+		assert!(intersects(diagonal(2.9), (0., 0.), aabb(4., 4.), (4., 4.)));
+	}
+
+	#[test]
+	fn extents_and_validity() {
+		let extents = capsule(0., 2., 4.).extents();
+		assert_eq!((extents.w, extents.h), (8., 12.));
+		let extents = capsule(-3., 0., 1.).extents();
+		assert_eq!((extents.w, extents.h), (8., 2.));
+		assert!(capsule(0., 0., 1.).is_valid());
+		assert!(!capsule(f32::NAN, 0., 1.).is_valid());
+		assert!(!circle(0.).is_valid());
+		assert!(!aabb(4., -4.).is_valid());
+		assert!(!aabb(f32::INFINITY, 4.).is_valid());
+	}
+}
@@ -10,9 +10,13 @@ use winit::{
 };
 
 use crate::{
-	coords::{text_box, Dimensions, Rect, RectI},
-	game::{Config, Game, GameInfo, MenuChoice},
-	gameplay::{Enemy, EnemyType, Player, ProjType, Projectile, World},
+	boss::Boss,
+	campaign::UnlockCondition,
+	clock::SimClock,
+	coords::{interpolate, text_box, Dimensions, Rect, RectI},
+	game::{Config, Game, GameInfo, MenuChoice, PauseChoice, ResultChoice},
+	gameplay::{Enemy, LevelEnd, Player, ProjType, World},
+	level::LevelError,
 };
 
 #[derive(Debug)]
@@ -32,12 +36,23 @@ pub const DRAW_CONSTANTS: DrawConstants = DrawConstants {
 
 pub const N_SIZES: u8 = DRAW_CONSTANTS.sizes.len() as u8;
 
+/// Named backgrounds that levels can use instead of a `#rrggbb` color
+pub const BACKGROUND_PALETTE: [(&str, [u8; 4]); 4] = [
+	("night", COLORS.bg),
+	("dusk", [0x2a, 0x10, 0x26, 0xff]),
+	("abyss", [0x00, 0x12, 0x1c, 0xff]),
+	("ember", [0x24, 0x0a, 0x04, 0xff]),
+];
+
 #[derive(Debug)]
 struct ColorPalette {
 	bg: [u8; 4],
 	bg_ui: [u8; 4],
 	menu_select: [u8; 4],
 	menu_text: [u8; 4],
+	menu_disabled: [u8; 4],
+	/// Drawn over the game under the pause menu
+	menu_overlay: [u8; 4],
 }
 
 const COLORS: ColorPalette = ColorPalette {
@@ -45,6 +60,8 @@ const COLORS: ColorPalette = ColorPalette {
 	bg_ui: [0x20, 0x11, 0x38, 0xff],
 	menu_select: [0xff, 0x00, 0x00, 0xff],
 	menu_text: [0xff, 0xff, 0xff, 0xff],
+	menu_disabled: [0x60, 0x60, 0x70, 0xff],
+	menu_overlay: [0x08, 0x0b, 0x1e, 0xc0],
 };
 
 #[derive(Debug)]
@@ -68,6 +85,9 @@ impl Sheets {
 
 pub const CHAR_DIMS: Dimensions<u32> = Dimensions { w: 4, h: 6 };
 
+/// Number of 8×8 sprites in a row and in a column of the spritesheet
+pub const SPRITESHEET_CELLS: Dimensions<u32> = Dimensions { w: 4, h: 4 };
+
 pub fn conv_srgb_to_linear(x: f64) -> f64 {
 	// See https://github.com/gfx-rs/wgpu/issues/2326
 	// Stolen from https://github.com/three-rs/three/blob/07e47da5e0673aa9a16526719e16debd59040eec/src/color.rs#L42
@@ -177,8 +197,9 @@ impl Game {
 	}
 
 	pub fn draw_in_game(&mut self) {
-		self.frame_buffer.fill_with_color(COLORS.bg);
 		let world = &mut self.world.as_mut().unwrap();
+		let background = world.level_meta.background.unwrap_or(COLORS.bg);
+		self.frame_buffer.fill_with_color(background);
 
 		world.draw_gameplay(&mut self.frame_buffer, &self.sheets, self.config.scale4);
 		world.draw_interface(
@@ -193,18 +214,28 @@ impl Game {
 		&mut self,
 		text: &str,
 		text_scale: (i32, i32),
-		mut dst: Point2<i32>,
+		dst: Point2<i32>,
 		selected: bool,
 	) {
-		let text = text.to_uppercase();
-		let text_dims = text_box(text.len(), 4) * text_scale;
-		// Centers text
-		dst.x -= text_dims.w / 2;
 		let color = if selected {
 			COLORS.menu_select
 		} else {
 			COLORS.menu_text
 		};
+		self.draw_centered_text(text, text_scale, dst, color);
+	}
+
+	fn draw_centered_text(
+		&mut self,
+		text: &str,
+		text_scale: (i32, i32),
+		mut dst: Point2<i32>,
+		color: [u8; 4],
+	) {
+		let text = text.to_uppercase();
+		let text_dims = text_box(text.len(), 4) * text_scale;
+		// Centers text
+		dst.x -= text_dims.w / 2;
 
 		draw_text(
 			&mut self.frame_buffer,
@@ -255,15 +286,91 @@ impl Game {
 			MenuChoice::Level(id) => {
 				self.draw_menu_entry("Level Selection", (5, 5), (base_x, title_y).into(), false);
 				// Gets the level list while dropping the mutable borrowing of `self`
-				let level_list: Vec<(u32, Rc<String>)> =
-					self.levels.iter().map(|x| (x.id, x.name.clone())).collect();
-
-				for (i, entry) in level_list.iter().enumerate() {
-					self.draw_menu_entry(
-						&entry.1,
+				let level_list: Vec<(Rc<String>, bool)> = self
+					.levels
+					.iter()
+					.map(|x| (x.name.clone(), self.is_unlocked(x)))
+					.collect();
+
+				for (i, (name, unlocked)) in level_list.iter().enumerate() {
+					let dst = (base_x, base_y + 100 * i as i32).into();
+					if *unlocked {
+						self.draw_menu_entry(name, (3, 3), dst, id == i as u16);
+					} else {
+						let color = if id == i as u16 {
+							COLORS.menu_select
+						} else {
+							COLORS.menu_disabled
+						};
+						self.draw_centered_text(&format!("{name} (locked)"), (3, 3), dst, color);
+					}
+				}
+				// Infos of the selected level
+				if let Some(level) = self.levels.get(id as usize) {
+					let meta = level.meta.clone();
+					let mut infos = vec![];
+					if let Some(author) = &meta.author {
+						infos.push(format!("by {author}"));
+					}
+					if let Some(difficulty) = meta.difficulty {
+						infos.push(format!("difficulty {difficulty}/5"));
+					}
+					if let Some(par_score) = meta.par_score {
+						infos.push(format!("par {par_score}"));
+					}
+					if let Some(time_limit) = meta.time_limit {
+						infos.push(format!("{}s", time_limit.as_secs()));
+					}
+					if !self.is_unlocked(level) {
+						// Shows what is needed to unlock the level instead
+						let conditions: Vec<String> = level
+							.unlock
+							.iter()
+							.map(|condition| match condition {
+								UnlockCondition::Clear(id) => {
+									format!("clear {}", self.levels[*id as usize].name)
+								},
+								UnlockCondition::Score(score) => format!("total score {score}"),
+								UnlockCondition::Missing(file) => format!("clear {file} (missing)"),
+							})
+							.collect();
+						infos = vec![format!("locked: {}", conditions.join(", "))];
+					}
+					let infos_y = title_y + 160;
+					self.draw_centered_text(
+						&infos.join(" - "),
+						(2, 2),
+						(base_x, infos_y).into(),
+						COLORS.menu_text,
+					);
+					if let Some(description) = &meta.description {
+						// Cuts the description to fit in the window
+						let max_len = (self.frame_buffer.dims.w / 16) as usize - 2;
+						let description: String = description.chars().take(max_len).collect();
+						self.draw_centered_text(
+							&description,
+							(1, 1),
+							(base_x, infos_y + 70).into(),
+							COLORS.menu_text,
+						);
+					}
+				}
+				// Levels that failed to load are listed after, but can't be selected
+				let invalid_list: Vec<String> = self
+					.invalid_levels
+					.iter()
+					.map(|x| match &x.error {
+						LevelError::Parse(err) => format!("{} (invalid, line {})", x.name, err.line),
+						LevelError::Events(_) => format!("{} (invalid events)", x.name),
+					})
+					.collect();
+				for (i, entry) in invalid_list.iter().enumerate() {
+					let i = (level_list.len() + i) as i32;
+					self.draw_centered_text(
+						entry,
 						(3, 3),
-						(base_x, base_y + 100 * i as i32).into(),
-						id == i as u16,
+						(base_x, base_y + 100 * i).into(),
+						COLORS.menu_disabled,
 					);
 				}
 			},
@@ -283,6 +390,112 @@ impl Game {
 			},
 		}
 	}
+
+	/// Draws the results of the level that ended over its last frame
+	pub fn draw_results(&mut self, choice: ResultChoice) {
+		let Some(results) = self.results.clone() else {
+			return;
+		};
+		let frame_buffer_dims = self.frame_buffer.dims;
+		draw_rect(
+			&mut self.frame_buffer,
+			frame_buffer_dims.into_rect(),
+			COLORS.menu_overlay,
+		);
+
+		let (base_x, base_y, title_y) = {
+			let win_w = frame_buffer_dims.w;
+			let win_h = frame_buffer_dims.h;
+			(win_w as i32 / 2, win_h as i32 / 2, win_h as i32 / 10)
+		};
+		let title = match results.end {
+			LevelEnd::Cleared => "Level cleared",
+			LevelEnd::Died => "Game over",
+			LevelEnd::TimeUp => "Time's up",
+		};
+		self.draw_menu_entry(title, (5, 5), (base_x, title_y).into(), false);
+
+		let score = if results.best_score {
+			format!("score {} (best)", results.score)
+		} else {
+			format!("score {}", results.score)
+		};
+		let stats = [
+			score,
+			format!("time {:.1}s", results.time.as_secs_f32()),
+			format!("accuracy {:.0}%", results.accuracy()),
+			format!("lives left {}", results.lives),
+		];
+		for (i, stat) in stats.iter().enumerate() {
+			let dst = (base_x, title_y + 140 + 50 * i as i32).into();
+			self.draw_centered_text(stat, (2, 2), dst, COLORS.menu_text);
+		}
+
+		let next_available = self.next_level_available();
+		for (i, (text, entry)) in [
+			("Retry", ResultChoice::Retry),
+			("Next level", ResultChoice::NextLevel),
+			("Back to level select", ResultChoice::LevelSelect),
+		]
+		.into_iter()
+		.enumerate()
+		{
+			let dst = (base_x, base_y + 100 * i as i32).into();
+			if entry == ResultChoice::NextLevel && !next_available {
+				self.draw_centered_text(text, (3, 3), dst, COLORS.menu_disabled);
+			} else {
+				self.draw_menu_entry(text, (3, 3), dst, choice == entry);
+			}
+		}
+	}
+
+	/// Draws the pause menu over the frame of the game
+	pub fn draw_pause_menu(&mut self, choice: PauseChoice) {
+		let frame_buffer_dims = self.frame_buffer.dims;
+		draw_rect(
+			&mut self.frame_buffer,
+			frame_buffer_dims.into_rect(),
+			COLORS.menu_overlay,
+		);
+
+		let (base_x, base_y, title_y) = {
+			let win_w = frame_buffer_dims.w;
+			let win_h = frame_buffer_dims.h;
+			(win_w as i32 / 2, win_h as i32 / 3, win_h as i32 / 10)
+		};
+
+		if choice == PauseChoice::Resolution {
+			self.draw_menu_entry("Resolution", (5, 5), (base_x, title_y).into(), false);
+			let res_choice = self.config.resolution_choice;
+			for (i, res) in DRAW_CONSTANTS.sizes.iter().enumerate() {
+				self.draw_menu_entry(
+					&format!("{:4} X {:4}", res.w, res.h),
+					(3, 3),
+					(base_x, base_y + 100 * i as i32).into(),
+					res_choice == i as u8,
+				);
+			}
+			return;
+		}
+
+		self.draw_menu_entry("Paused", (5, 5), (base_x, title_y).into(), false);
+		for (i, (text, entry)) in [
+			("Resume", PauseChoice::Resume),
+			("Restart level", PauseChoice::Restart),
+			("Options", PauseChoice::Options),
+			("Quit to menu", PauseChoice::Quit),
+		]
+		.into_iter()
+		.enumerate()
+		{
+			self.draw_menu_entry(
+				text,
+				(3, 3),
+				(base_x, base_y + 100 * i as i32).into(),
+				choice == entry,
+			);
+		}
+	}
 }
 
 macro_rules! opacity {
@@ -291,7 +504,7 @@ macro_rules! opacity {
 	};
 }
 
-pub fn draw_rect(frame_buffer: &mut FrameBuffer, dst: RectI, mut color: [u8; 4]) {
+pub fn draw_rect(frame_buffer: &mut FrameBuffer, dst: RectI, color: [u8; 4]) {
 	let frame_buffer_dims = frame_buffer.dims;
 	// Transparent
 	if color[3] == 0x00 {
@@ -303,6 +516,8 @@ pub fn draw_rect(frame_buffer: &mut FrameBuffer, dst: RectI, mut color: [u8; 4])
 			let pixel_index = coords.y * frame_buffer_dims.w as i32 + coords.x;
 			let pixel_byte_index = pixel_index as usize * 4;
 			let pixel_bytes = pixel_byte_index..(pixel_byte_index + 4);
+			// Blended with each pixel, the color given stays the same for the next ones
+			let mut px = color;
 			if color[3] != 0xff {
 				let old_color = frame_buffer
 					.buffer
@@ -310,12 +525,12 @@ pub fn draw_rect(frame_buffer: &mut FrameBuffer, dst: RectI, mut color: [u8; 4])
 					.get(pixel_bytes.clone())
 					.unwrap();
 				let alpha = color[3] as f32 / 255.;
-				color[0] = opacity!(color, old_color, alpha, 0);
-				color[1] = opacity!(color, old_color, alpha, 1);
-				color[2] = opacity!(color, old_color, alpha, 2);
-				color[3] = 0xff;
+				px[0] = opacity!(color, old_color, alpha, 0);
+				px[1] = opacity!(color, old_color, alpha, 1);
+				px[2] = opacity!(color, old_color, alpha, 2);
+				px[3] = 0xff;
 			}
-			frame_buffer.buffer.frame_mut()[pixel_bytes].copy_from_slice(&color);
+			frame_buffer.buffer.frame_mut()[pixel_bytes].copy_from_slice(&px);
 		}
 	}
 }
@@ -338,7 +553,7 @@ fn char_position(c: char) -> Option<(u32, u32)> {
 		ch if sixth_line.contains(ch) => {
 			Some((sixth_line.chars().position(|c| c == ch).unwrap() as u32, 5))
 		},
-		_ => unimplemented!("Character {c} doesn't exist in font"),
+		_ => None,
 	}
 }
 
@@ -372,7 +587,11 @@ fn draw_text(
 		draw_sprite(
 			frame_buffer,
 			font_sheet,
-			SpriteCoords { sheet_pos: char_position(c).unwrap().into(), dims: (4, 6).into() },
+			// Characters missing from the font are replaced by '?'
+			SpriteCoords {
+				sheet_pos: char_position(c).or(char_position('?')).unwrap().into(),
+				dims: (4, 6).into(),
+			},
 			dst_c,
 			Some(color),
 		);
@@ -429,9 +648,14 @@ fn draw_sprite(
 }
 
 impl Player {
-	fn sprite_coords(&self) -> SpriteCoords {
+	fn sprite_coords(&self, clock: &SimClock) -> SpriteCoords {
 		SpriteCoords {
-			sheet_pos: if self.immunity_over() { (1, 0) } else { (1, 1) }.into(),
+			sheet_pos: if self.immunity_over(clock) {
+				(1, 0)
+			} else {
+				(1, 1)
+			}
+			.into(),
 			dims: (8, 8).into(),
 		}
 	}
@@ -441,23 +665,22 @@ impl Player {
 	}
 }
 
-impl Enemy {
+impl Boss {
 	fn sprite_coords(&self) -> SpriteCoords {
-		SpriteCoords {
-			sheet_pos: match self.variant {
-				EnemyType::Basic => (2, 0),
-				EnemyType::Sniper => (3, 0),
-			}
-			.into(),
-			dims: (8, 8).into(),
-		}
+		SpriteCoords { sheet_pos: (2, 0).into(), dims: (8, 8).into() }
 	}
 }
 
-impl Projectile {
+impl Enemy {
 	fn sprite_coords(&self) -> SpriteCoords {
+		SpriteCoords { sheet_pos: self.def.sprite, dims: (8, 8).into() }
+	}
+}
+
+impl ProjType {
+	fn sprite_coords(self) -> SpriteCoords {
 		SpriteCoords {
-			sheet_pos: match self.variant {
+			sheet_pos: match self {
 				ProjType::Basic => (2, 1),
 				ProjType::Aimed => (3, 1),
 				ProjType::PlayerShoot => (0, 1),
@@ -469,15 +692,18 @@ impl Projectile {
 }
 
 impl World {
+	/// Draws the entities between their positions at the previous tick and the current one
 	pub fn draw_gameplay(&self, frame_buffer: &mut FrameBuffer, sheets: &Sheets, scale4: u32) {
 		let scale = scale4 as f32 / 4.;
+		let alpha = self.clock.alpha();
 		// Player
 		let player = &self.player;
+		let player_pos = interpolate(player.prev_pos, player.pos, alpha);
 		draw_sprite(
 			frame_buffer,
 			&sheets.spritesheet,
-			player.sprite_coords(),
-			Rect::from_float_scale(player.pos, player.size, scale),
+			player.sprite_coords(&self.clock),
+			Rect::from_float_scale(player_pos, player.size, scale),
 			None,
 		);
 		// Player hitbox
@@ -485,43 +711,59 @@ impl World {
 			frame_buffer,
 			&sheets.spritesheet,
 			player.sprite_coords_hit(),
-			Rect::from_float_scale(player.pos, player.hitbox.dims, scale),
+			Rect::from_float_scale(player_pos, player.hitbox.extents(), scale),
 			None,
 		);
 
 		// Enemies
 		for enemy in self.enemies.iter() {
+			let pos = interpolate(enemy.prev_pos, enemy.pos, alpha);
 			draw_sprite(
 				frame_buffer,
 				&sheets.spritesheet,
 				enemy.sprite_coords(),
-				Rect::from_float_scale(enemy.pos, enemy.size, scale),
+				Rect::from_float_scale(pos, enemy.size, scale),
 				None,
 			);
 			draw_rect(
 				frame_buffer,
-				Rect::life_bar_full(enemy.pos, enemy.size).scale4(scale4),
+				Rect::life_bar_full(pos, enemy.size).scale4(scale4),
 				[0xff, 0x00, 0x00, 0xff],
 			);
 			draw_rect(
 				frame_buffer,
-				Rect::life_bar(
-					enemy.pos,
-					enemy.size,
-					enemy.hp / Enemy::max_hp(enemy.variant),
-				)
-				.scale4(scale4),
+				Rect::life_bar(pos, enemy.size, enemy.hp / enemy.def.hp).scale4(scale4),
 				[0x00, 0xff, 0x00, 0xff],
 			);
 		}
 
+		// Bosses, their life bar is in the interface
+		for boss in self.bosses.iter() {
+			draw_sprite(
+				frame_buffer,
+				&sheets.spritesheet,
+				boss.sprite_coords(),
+				Rect::from_float_scale(
+					interpolate(boss.prev_pos, boss.pos, alpha),
+					boss.size,
+					scale,
+				),
+				None,
+			);
+		}
+
 		//projectiles
-		for proj in self.projectiles.iter() {
+		let projectiles = &self.projectiles;
+		for slot in projectiles.slots() {
 			draw_sprite(
 				frame_buffer,
 				&sheets.spritesheet,
-				proj.sprite_coords(),
-				Rect::from_float_scale(proj.pos, Dimensions { w: 10., h: 10. }, scale),
+				projectiles.variant[slot].sprite_coords(),
+				Rect::from_float_scale(
+					interpolate(projectiles.prev_pos[slot], projectiles.pos[slot], alpha),
+					Dimensions { w: 10., h: 10. },
+					scale,
+				),
 				None,
 			);
 		}
@@ -587,7 +829,20 @@ impl World {
 			&score_str,
 		);
 
-		let level_name = "LEVEL 1";
+		if let Some(par_score) = self.level_meta.par_score {
+			let par_str = format!("PAR: {par_score:3}");
+			let par_dims = text_box(par_str.len(), TEXT_SCALE);
+			draw_text(
+				frame_buffer,
+				&sheets.font,
+				Rect { top_left: (win_w - par_dims.w, 84).into(), dims: par_dims }
+					.to_interface(0, scale4),
+				[0xff, 0xff, 0xff, 0xb0],
+				&par_str,
+			);
+		}
+
+		let level_name = self.level_name.to_uppercase();
 		draw_text(
 			frame_buffer,
 			&sheets.font,
@@ -597,7 +852,72 @@ impl World {
 			}
 			.to_interface(interf_begin_x as i32, scale4),
 			[0xff, 0x00, 0x00, 0xff],
-			level_name,
+			&level_name,
 		);
+
+		if let Some(time_left) = self.time_left() {
+			let time_str = format!("TIME: {:3}", time_left.as_secs_f32().ceil() as u32);
+			draw_text(
+				frame_buffer,
+				&sheets.font,
+				Rect {
+					top_left: (20, 260).into(),
+					dims: text_box(time_str.len(), TEXT_SCALE),
+				}
+				.to_interface(interf_begin_x as i32, scale4),
+				[0xff, 0xff, 0xff, 0xff],
+				&time_str,
+			);
+		}
+
+		// Boss life bar, with a mark at the end of each phase
+		if let Some(boss) = self.bosses.first() {
+			let boss_name = boss.def.name.to_uppercase();
+			draw_text(
+				frame_buffer,
+				&sheets.font,
+				Rect {
+					top_left: (20, 320).into(),
+					dims: text_box(boss_name.len(), TEXT_SCALE),
+				}
+				.to_interface(interf_begin_x as i32, scale4),
+				[0xff, 0x00, 0x00, 0xff],
+				&boss_name,
+			);
+			const BAR_W: f32 = 280.;
+			let max_hp = boss.def.max_hp();
+			let bar = |begin: f32, end: f32| {
+				Rect {
+					top_left: ((20. + BAR_W * begin).round() as i32, 360).into(),
+					dims: (((end - begin) * BAR_W).round() as i32, 16).into(),
+				}
+				.to_interface(interf_begin_x as i32, scale4)
+			};
+			draw_rect(frame_buffer, bar(0., 1.), [0xff, 0x00, 0x00, 0xff]);
+			draw_rect(
+				frame_buffer,
+				bar(0., boss.hp.max(0.) / max_hp),
+				[0x00, 0xff, 0x00, 0xff],
+			);
+			for phase in 0..boss.def.phases.len() - 1 {
+				let threshold = boss.def.threshold(phase) / max_hp;
+				draw_rect(
+					frame_buffer,
+					bar(threshold, threshold + 0.01),
+					[0xff, 0xff, 0xff, 0xff],
+				);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn spritesheet_cells() {
+		let (w, h) = Sheets::load().spritesheet.dimensions();
+		assert_eq!((w, h), (SPRITESHEET_CELLS.w * 8, SPRITESHEET_CELLS.h * 8));
 	}
 }
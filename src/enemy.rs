@@ -0,0 +1,502 @@
+use cgmath::{InnerSpace, Point2, Vector2};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, f32::consts::PI, fs, path::Path, rc::Rc, time::Duration};
+
+use crate::{
+	coords::{Dimensions, Shape},
+	draw::SPRITESHEET_CELLS,
+	gameplay::ProjType,
+	level::{is_label, parse_interval, LevelParseError, LineTokens, ParseErrorKind},
+	pattern::{parse_actions, vector_angle, waits, Action, BulletPattern, Direction},
+};
+
+/// Directory of the enemy definition files
+pub const ENEMY_DIR: &str = "./enemies";
+
+/// Movement of an enemy without a path once it is on screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnemyMovement {
+	/// Keeps going down
+	Down,
+	/// Goes down and away from the middle of the screen
+	Diverge,
+	/// Turns around the top middle of the screen
+	Orbit,
+}
+
+/// Directions of the bullets shot at once, used by enemies and bosses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShotPattern {
+	/// One bullet going down
+	Down,
+	/// One bullet at the player
+	Aimed,
+	/// Fan of bullets going down
+	Spread,
+	/// Bullets in every direction
+	Ring,
+}
+
+impl ShotPattern {
+	pub fn directions(self, from: Point2<f32>, player_pos: Point2<f32>) -> Vec<Vector2<f32>> {
+		match self {
+			ShotPattern::Down => vec![Vector2::unit_y()],
+			ShotPattern::Aimed => {
+				let to_player = player_pos - from;
+				if to_player == Vector2::new(0., 0.) {
+					vec![Vector2::unit_y()]
+				} else {
+					vec![to_player.normalize()]
+				}
+			},
+			ShotPattern::Spread => (-2..=2)
+				.map(|i| {
+					let angle = PI / 2. + i as f32 * PI / 12.;
+					Vector2::new(angle.cos(), angle.sin())
+				})
+				.collect(),
+			ShotPattern::Ring => (0..16)
+				.map(|i| {
+					let angle = i as f32 * PI / 8.;
+					Vector2::new(angle.cos(), angle.sin())
+				})
+				.collect(),
+		}
+	}
+
+	/// Bullets going straight down look different from the others
+	pub fn proj_type(self) -> ProjType {
+		match self {
+			ShotPattern::Down => ProjType::Basic,
+			_ => ProjType::Aimed,
+		}
+	}
+
+	/// Pattern firing the bullets at once then waiting for `interval`
+	fn to_bullet_pattern(self, name: &str, speed: f32, interval: Duration) -> BulletPattern {
+		let fire = |dir| Action::Fire { speed, dir, actions: None };
+		let mut actions: Vec<Action> = match self {
+			ShotPattern::Aimed => vec![fire(Direction::Aim(0.))],
+			pattern => pattern
+				.directions(Point2::new(0., 0.), Point2::new(0., 0.))
+				.into_iter()
+				.map(|dir| fire(Direction::Absolute(vector_angle(dir))))
+				.collect(),
+		};
+		actions.push(Action::Wait(interval));
+		BulletPattern {
+			name: name.into(),
+			actions: actions.into(),
+			variant: self.proj_type(),
+		}
+	}
+}
+
+/// Enemy archetype, read from the `.hbe` files of the enemies directory
+#[derive(Clone, Debug)]
+pub struct EnemyDef {
+	pub name: String,
+	/// Size of the sprite
+	pub size: Dimensions<f32>,
+	/// Box of the size of the sprite if the enemy type doesn't give one
+	pub hitbox: Shape,
+	pub hp: f32,
+	/// Cell of the sprite in the spritesheet
+	pub sprite: Point2<u32>,
+	pub movement: EnemyMovement,
+	/// Enemies without pattern never shoot
+	pub pattern: Option<Rc<BulletPattern>>,
+	/// Points given when the enemy is killed
+	pub score: u64,
+}
+
+/// Enemy definitions by name
+#[derive(Clone, Debug, Default)]
+pub struct EnemyTypes {
+	defs: HashMap<String, Rc<EnemyDef>>,
+	/// Bullet patterns defined with `@pattern`, by name
+	patterns: HashMap<String, Rc<BulletPattern>>,
+}
+
+impl EnemyTypes {
+	/// Reads the `.hbe` files of the directory
+	pub fn load(dir: &Path) -> Result<EnemyTypes, LevelParseError> {
+		let io_error = |file: &Path, err: std::io::Error| LevelParseError {
+			file: file.to_path_buf(),
+			line: 0,
+			column: 0,
+			kind: ParseErrorKind::Io(err.to_string()),
+		};
+		let mut files: Vec<_> = fs::read_dir(dir)
+			.map_err(|err| io_error(dir, err))?
+			.filter_map(|entry| Some(entry.ok()?.path()))
+			.filter(|path| path.extension().is_some_and(|ext| ext == "hbe"))
+			.collect();
+		files.sort();
+		let mut types = EnemyTypes::default();
+		for file in files {
+			let raw_data = fs::read_to_string(&file).map_err(|err| io_error(&file, err))?;
+			types.parse(&file, &raw_data)?;
+		}
+		Ok(types)
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Rc<EnemyDef>> {
+		self.defs.get(name)
+	}
+
+	/// Names of the enemies, sorted
+	pub fn names(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self.defs.keys().map(String::as_str).collect();
+		names.sort();
+		names
+	}
+
+	/// Reads the `@enemy <name> { ... }` and `@pattern <name> { ... }` blocks of a file
+	fn parse(&mut self, file: &Path, raw_data: &str) -> Result<(), LevelParseError> {
+		let mut lines = raw_data.lines().enumerate().map(|(i, line)| {
+			let content = line.trim_start();
+			(
+				i + 1,
+				line.chars().count() - content.chars().count() + 1,
+				content.trim_end(),
+			)
+		});
+		while let Some((number, column, content)) = lines.next() {
+			let Some(entry) = content.strip_prefix('@') else {
+				continue;
+			};
+			let mut tokens = LineTokens::new(file, number, entry, column);
+			const KEY_EXPECTED: &str = "enemy or pattern definition (enemy, pattern)";
+			let (col, key) = tokens.next(KEY_EXPECTED)?;
+			if key != "enemy" && key != "pattern" {
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken { expected: KEY_EXPECTED, found: key.into() },
+				));
+			}
+			const NAME_EXPECTED: &str = "name (letters, digits, '-' or '_')";
+			let (name_col, name) = tokens.next(NAME_EXPECTED)?;
+			if !is_label(name) {
+				return Err(tokens.error(
+					name_col,
+					ParseErrorKind::InvalidToken { expected: NAME_EXPECTED, found: name.into() },
+				));
+			}
+			let (open_col, open) = tokens.next("'{' opening the definition")?;
+			if open != "{" {
+				return Err(tokens.error(
+					open_col,
+					ParseErrorKind::InvalidToken {
+						expected: "'{' opening the definition",
+						found: open.into(),
+					},
+				));
+			}
+			tokens.finish()?;
+
+			if key == "pattern" {
+				if self.patterns.contains_key(name) {
+					return Err(LevelParseError {
+						file: file.to_path_buf(),
+						line: number,
+						column: name_col,
+						kind: ParseErrorKind::DuplicatePattern(name.into()),
+					});
+				}
+				let actions = parse_actions(file, &mut lines, (number, open_col))?;
+				// The pattern of an enemy starts again as soon as it ends
+				if !waits(&actions) {
+					return Err(LevelParseError {
+						file: file.to_path_buf(),
+						line: number,
+						column: name_col,
+						kind: ParseErrorKind::InvalidToken {
+							expected: "pattern with a wait",
+							found: name.into(),
+						},
+					});
+				}
+				let pattern = BulletPattern { name: name.into(), actions, variant: ProjType::Aimed };
+				self.patterns.insert(name.into(), Rc::new(pattern));
+				continue;
+			}
+			if self.defs.contains_key(name) {
+				return Err(LevelParseError {
+					file: file.to_path_buf(),
+					line: number,
+					column: name_col,
+					kind: ParseErrorKind::DuplicateEnemy(name.into()),
+				});
+			}
+			let (mut size, mut hitbox, mut hp, mut sprite, mut movement, mut pattern) =
+				(None, None, None, None, None, None);
+			let mut score = 100;
+			let mut closed = None;
+			const PROPERTY_EXPECTED: &str =
+				"enemy property (size, hitbox, hp, sprite, movement, shot, pattern, score)";
+			for (number, column, content) in lines.by_ref() {
+				if content == "}" {
+					closed = Some((number, column));
+					break;
+				}
+				let Some(data) = content.strip_prefix('$') else {
+					continue;
+				};
+				let mut tokens = LineTokens::new(file, number, data, column);
+				let (col, key) = tokens.next("enemy property")?;
+				match key {
+					"size" => {
+						let w = tokens.parse_positive("width (positive number)")?;
+						let h = tokens.parse_positive("height (positive number)")?;
+						size = Some(Dimensions { w, h });
+					},
+					"hitbox" => hitbox = Some(parse_shape(&mut tokens)?),
+					"hp" => hp = Some(tokens.parse_positive("hp (positive number)")?),
+					"sprite" => {
+						let x = parse_cell(&mut tokens, "sprite column (0 to 3)", SPRITESHEET_CELLS.w)?;
+						let y = parse_cell(&mut tokens, "sprite row (0 to 3)", SPRITESHEET_CELLS.h)?;
+						sprite = Some(Point2::new(x, y));
+					},
+					"movement" => {
+						let (col, token) = tokens.next("movement")?;
+						movement = Some(match token {
+							"down" => EnemyMovement::Down,
+							"diverge" => EnemyMovement::Diverge,
+							"orbit" => EnemyMovement::Orbit,
+							other => {
+								return Err(tokens.error(
+									col,
+									ParseErrorKind::InvalidToken {
+										expected: "movement (down, diverge, orbit)",
+										found: other.into(),
+									},
+								))
+							},
+						});
+					},
+					"shot" => {
+						let (col, token) = tokens.next("shot pattern")?;
+						let shot = parse_pattern(token).ok_or_else(|| {
+							tokens.error(
+								col,
+								ParseErrorKind::InvalidToken {
+									expected: "shot pattern (down, aimed, spread, ring)",
+									found: token.into(),
+								},
+							)
+						})?;
+						let speed = tokens
+							.parse_positive("bullet speed in pixels per second (positive number)")?;
+						let interval = parse_interval(&mut tokens)?;
+						pattern = Some(Rc::new(shot.to_bullet_pattern(name, speed, interval)));
+					},
+					"pattern" => {
+						let (col, token) = tokens.next("pattern name")?;
+						match self.patterns.get(token) {
+							Some(defined) => pattern = Some(defined.clone()),
+							None => {
+								return Err(tokens.error(col, ParseErrorKind::UnknownPattern(token.into())))
+							},
+						}
+					},
+					"score" => score = tokens.parse("score")?,
+					other => {
+						return Err(tokens.error(
+							col,
+							ParseErrorKind::InvalidToken {
+								expected: PROPERTY_EXPECTED,
+								found: other.into(),
+							},
+						))
+					},
+				}
+				tokens.finish()?;
+			}
+
+			let Some((end_line, end_col)) = closed else {
+				return Err(LevelParseError {
+					file: file.to_path_buf(),
+					line: number,
+					column: open_col,
+					kind: ParseErrorKind::UnclosedBlock,
+				});
+			};
+			let missing = |expected| LevelParseError {
+				file: file.to_path_buf(),
+				line: end_line,
+				column: end_col,
+				kind: ParseErrorKind::MissingToken { expected },
+			};
+			let size = size.ok_or_else(|| missing("enemy size ($size)"))?;
+			let def = EnemyDef {
+				name: name.into(),
+				size,
+				hitbox: hitbox.unwrap_or(Shape::Aabb(size)),
+				hp: hp.ok_or_else(|| missing("enemy hp ($hp)"))?,
+				sprite: sprite.ok_or_else(|| missing("enemy sprite ($sprite)"))?,
+				movement: movement.ok_or_else(|| missing("enemy movement ($movement)"))?,
+				pattern,
+				score,
+			};
+			self.defs.insert(name.into(), Rc::new(def));
+		}
+		Ok(())
+	}
+}
+
+/// Parses `circle <radius>`, `box <width> <height>` or `capsule <x> <y> <radius>`, the capsule
+/// going from `-x -y` to `x y` around the center
+pub fn parse_shape(tokens: &mut LineTokens) -> Result<Shape, LevelParseError> {
+	const EXPECTED: &str = "hitbox shape (circle, box, capsule)";
+	const RADIUS_EXPECTED: &str = "radius (positive number)";
+	let (col, kind) = tokens.next(EXPECTED)?;
+	Ok(match kind {
+		"circle" => Shape::Circle { radius: tokens.parse_positive(RADIUS_EXPECTED)? },
+		"box" => {
+			let w = tokens.parse_positive("width (positive number)")?;
+			let h = tokens.parse_positive("height (positive number)")?;
+			Shape::Aabb(Dimensions { w, h })
+		},
+		"capsule" => {
+			let x = tokens.parse_finite("horizontal half length")?;
+			let y = tokens.parse_finite("vertical half length")?;
+			Shape::Capsule {
+				half: Vector2::new(x, y),
+				radius: tokens.parse_positive(RADIUS_EXPECTED)?,
+			}
+		},
+		other => {
+			return Err(tokens.error(
+				col,
+				ParseErrorKind::InvalidToken { expected: EXPECTED, found: other.into() },
+			))
+		},
+	})
+}
+
+/// Writes a shape like `parse_shape` reads it
+pub fn format_shape(shape: &Shape) -> String {
+	match shape {
+		Shape::Circle { radius } => format!("circle {radius}"),
+		Shape::Aabb(Dimensions { w, h }) => format!("box {w} {h}"),
+		Shape::Capsule { half, radius } => format!("capsule {} {} {radius}", half.x, half.y),
+	}
+}
+
+/// Parses a column or a row of the spritesheet, lower than `cells`
+fn parse_cell(
+	tokens: &mut LineTokens,
+	expected: &'static str,
+	cells: u32,
+) -> Result<u32, LevelParseError> {
+	let (col, token) = tokens.next(expected)?;
+	match token.parse() {
+		Ok(cell) if cell < cells => Ok(cell),
+		_ => Err(tokens.error(
+			col,
+			ParseErrorKind::InvalidToken { expected, found: token.into() },
+		)),
+	}
+}
+
+pub fn parse_pattern(token: &str) -> Option<ShotPattern> {
+	match token {
+		"down" => Some(ShotPattern::Down),
+		"aimed" => Some(ShotPattern::Aimed),
+		"spread" => Some(ShotPattern::Spread),
+		"ring" => Some(ShotPattern::Ring),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(content: &str) -> Result<EnemyTypes, LevelParseError> {
+		let mut types = EnemyTypes::default();
+		types.parse(Path::new("test.hbe"), content)?;
+		Ok(types)
+	}
+
+	/// Parses an enemy with the property line `property`, on line 2
+	fn parse_property(property: &str) -> Result<EnemyTypes, LevelParseError> {
+		let mut lines = vec!["$size 32 32", "$hp 10", "$sprite 2 0", "$movement down"];
+		lines.retain(|line| line.split(' ').next() != property.split(' ').next());
+		lines.insert(0, property);
+		parse(&format!("@enemy test {{\n{}\n}}\n", lines.join("\n")))
+	}
+
+	#[test]
+	fn valid_enemy() {
+		let types = parse_property("$hitbox capsule 0 -2.5 4").unwrap();
+		let def = &types.defs["test"];
+		assert_eq!((def.size.w, def.size.h), (32., 32.));
+		assert_eq!(def.hp, 10.);
+		assert_eq!(def.sprite, Point2::new(2, 0));
+		assert!(matches!(
+			def.hitbox,
+			Shape::Capsule { half, radius: 4. } if half == Vector2::new(0., -2.5)
+		));
+		assert_eq!(def.score, 100);
+	}
+
+	#[test]
+	fn invalid_values() {
+		for (property, column, found) in [
+			("$hp 0", 5, "0"),
+			("$hp -3", 5, "-3"),
+			("$hp NaN", 5, "NaN"),
+			("$hp inf", 5, "inf"),
+			("$size -32 32", 7, "-32"),
+			("$size 32 0", 10, "0"),
+			("$sprite 4 0", 9, "4"),
+			("$sprite 0 -1", 11, "-1"),
+			("$hitbox circle 0", 16, "0"),
+			("$hitbox box 8 NaN", 15, "NaN"),
+			("$hitbox capsule inf 2 4", 17, "inf"),
+			("$hitbox capsule 0 2 -4", 21, "-4"),
+			("$shot down -600 0.5", 12, "-600"),
+			("$shot down 600 0", 16, "0"),
+			("$shot aimed 600 -1", 17, "-1"),
+		] {
+			let err = parse_property(property).unwrap_err();
+			assert_eq!((err.line, err.column), (2, column), "{property}");
+			match err.kind {
+				ParseErrorKind::InvalidToken { found: token, .. } => assert_eq!(token, found),
+				kind => panic!("{property}: {kind:?}"),
+			}
+		}
+	}
+
+	#[test]
+	fn shots_wait_between_bullets() {
+		for shot in [
+			ShotPattern::Down,
+			ShotPattern::Aimed,
+			ShotPattern::Spread,
+			ShotPattern::Ring,
+		] {
+			let pattern = shot.to_bullet_pattern("shot", 600., Duration::from_millis(1));
+			assert!(waits(&pattern.actions), "{shot:?}");
+		}
+	}
+
+	#[test]
+	fn missing_property() {
+		let err = parse("@enemy test {\n$size 32 32\n$hp 10\n$movement down\n}\n").unwrap_err();
+		assert_eq!((err.line, err.column), (5, 1));
+		assert!(matches!(
+			err.kind,
+			ParseErrorKind::MissingToken { expected: "enemy sprite ($sprite)" }
+		));
+	}
+
+	#[test]
+	fn shipped_enemies() {
+		let types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		assert!(!types.defs.is_empty());
+	}
+}
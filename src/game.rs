@@ -1,20 +1,25 @@
 use smol_str::SmolStr;
 use std::{
 	fs,
-	path::Path,
+	path::{Path, PathBuf},
 	rc::Rc,
-	time::{Duration, Instant},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use winit::{event::ElementState, event_loop::ActiveEventLoop, keyboard::Key, window::Window};
 
 use crate::{
+	campaign::{self, Campaign, Progress, SAVE_FILE},
 	coords::Dimensions,
 	draw::{create_window, FrameBuffer, ResizableWindow, Sheets, DRAW_CONSTANTS},
-	gameplay::{Cooldown, EnemyType, Event, EventType, World},
+	enemy::{EnemyTypes, ENEMY_DIR},
+	gameplay::{LevelEnd, World},
+	level::{is_level_file, InvalidLevel, Level, LevelError},
+	replay::{Replay, ReplayPlayer, REPLAY_DIR},
 	sound::{Audio, SoundBase},
 };
 
-const WORLD_SIZE: Dimensions<f32> = Dimensions {
+/// Size of the play area, the graphics are scaled up for higher resolutions
+pub const WORLD_SIZE: Dimensions<f32> = Dimensions {
 	w: DRAW_CONSTANTS.sizes[0].w as f32 * 0.75,
 	h: DRAW_CONSTANTS.sizes[0].h as f32,
 };
@@ -22,9 +27,12 @@ const WORLD_SIZE: Dimensions<f32> = Dimensions {
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum RunState {
 	Playing,
-	_Paused,
+	/// The world is kept but doesn't advance, the pause menu is drawn over it
+	Paused(PauseChoice),
 	Menu(MenuChoice),
-	_GameOver,
+	/// The player died or ran out of time, the results are shown over the last frame
+	GameOver(ResultChoice),
+	Victory(ResultChoice),
 	Quitting,
 }
 
@@ -41,79 +49,47 @@ pub enum MenuChoice {
 	Resolution,
 }
 
-#[derive(Clone, Debug)]
-pub struct Level {
-	pub id: u32,
-	pub name: Rc<String>,
-	event_list: Vec<Event>,
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PauseChoice {
+	Resume,
+	Restart,
+	Options,
+	Quit,
+	// Options menu
+	Resolution,
 }
 
-pub const LEVEL_REF: u32 = u32::MAX;
-impl Level {
-	fn level_parser(game: &mut Game, level_file: &str) {
-		let level_raw_data = fs::read_to_string(level_file).unwrap();
-		let mut level = Level {
-			id: game.levels.len() as u32,
-			event_list: vec![],
-			name: Rc::new(String::new()),
-		};
-
-		let meta_data = level_raw_data
-			.split('\n')
-			.filter_map(|x| x.strip_prefix('$'));
-
-		for data in meta_data {
-			let data = data.split_once(char::is_whitespace).unwrap();
-			match data.0 {
-				"title" => {
-					level.name = Rc::new(data.1.into());
-				},
-				data => {
-					unimplemented!("'{data}' keyword doesn't exist")
-				},
-			}
-		}
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ResultChoice {
+	Retry,
+	NextLevel,
+	LevelSelect,
+}
 
-		let events = level_raw_data
-			.split('\n')
-			.filter_map(|x| x.strip_prefix('@'));
-		let id: u32 = 0;
-		for event in events {
-			let mut event = event.split_whitespace();
-			match event.next().unwrap() {
-				"spawn-enemy" => {
-					let variant = match event.next().unwrap() {
-						"basic" => EnemyType::Basic,
-						"sniper" => EnemyType::Sniper,
-						other => unimplemented!("Enemy type '{other}' doesn't exist"),
-					};
-					let t: f32 = event.next().unwrap().parse().unwrap();
-					let t = Duration::from_secs_f32(t);
-					let x: f32 = event.next().unwrap().parse().unwrap();
-					let y: f32 = event.next().unwrap().parse().unwrap();
-					let ref_evt = event.next().unwrap().parse::<u32>().ok().map(|x| (x, t));
-					let variant = EventType::_SpawnEnemy((x, y).into(), variant);
-					// Events are all relative, the "absolute" events will be relative to the beginning of the level
-					let evt = match ref_evt {
-						Some(_) => Event { id, time: None, variant, ref_evt },
-						None => Event { id, time: None, variant, ref_evt: Some((LEVEL_REF, t)) },
-					};
-					level.event_list.push(evt);
-				},
-				evt => unimplemented!("Unknown event '{evt}'"),
-			}
-		}
-		game.levels.push(level);
-	}
+/// Statistics of a finished level, shown on the result screen
+#[derive(Clone, Debug)]
+pub struct LevelResults {
+	pub end: LevelEnd,
+	pub score: u64,
+	pub time: Duration,
+	pub shots_fired: u32,
+	pub shots_hit: u32,
+	pub lives: u32,
+	/// The score is the best one of the level
+	pub best_score: bool,
 }
 
-impl PartialEq for Level {
-	fn eq(&self, other: &Self) -> bool {
-		self.id == other.id
+impl LevelResults {
+	/// Share of the shots of the player that hit, in percent
+	pub fn accuracy(&self) -> f32 {
+		if self.shots_fired == 0 {
+			return 0.;
+		}
+		100. * self.shots_hit as f32 / self.shots_fired as f32
 	}
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Inputs {
 	pub left: bool,
 	pub right: bool,
@@ -143,13 +119,17 @@ impl Config {
 	}
 }
 
+/// Time between two refreshes of the FPS counter, for it to be readable
+const FPS_REFRESH: Duration = Duration::from_millis(100);
+
 #[derive(Clone, Debug)]
 pub struct GameInfo {
 	_game_begin: Instant,
 	level_begin: Option<Instant>,
 	frame_count: u64,
 	pub fps: u32,
-	fps_cooldown: Cooldown,
+	fps_refresh: Instant,
+	/// Duration of the last frame
 	pub dt: Duration,
 	pub t: Instant,
 }
@@ -161,7 +141,7 @@ impl GameInfo {
 			level_begin: None,
 			frame_count: 0,
 			fps: 0,
-			fps_cooldown: Cooldown::with_secs(0.1),
+			fps_refresh: Instant::now(),
 			dt: Duration::from_secs(1),
 			t: Instant::now(),
 		}
@@ -187,12 +167,21 @@ impl GameInfo {
 pub struct Game {
 	pub state: RunState,
 	pub world: Option<World>,
+	/// Results of the level that just ended
+	pub results: Option<LevelResults>,
 	pub inputs: Inputs,
+	/// Inputs of the level being played, saved in the replay directory when it ends
+	recording: Option<Replay>,
+	/// Replay giving the inputs instead of the keyboard
+	playback: Option<ReplayPlayer>,
 	pub window: Window,
 	pub frame_buffer: FrameBuffer,
 	pub sheets: Sheets,
 	pub audio: Audio,
+	pub enemy_types: Rc<EnemyTypes>,
 	pub levels: Vec<Level>,
+	pub invalid_levels: Vec<InvalidLevel>,
+	pub progress: Progress,
 	pub config: Config,
 	pub infos: GameInfo,
 }
@@ -204,31 +193,88 @@ impl Game {
 		Game {
 			state: RunState::Menu(MenuChoice::Play),
 			world: None,
+			results: None,
 			inputs: Inputs::new(),
+			recording: None,
+			playback: None,
 			frame_buffer: FrameBuffer::new(&window),
 			window,
 			sheets: Sheets::load(),
 			audio: Audio::new(),
+			enemy_types: Rc::default(),
 			levels: vec![],
+			invalid_levels: vec![],
+			progress: Progress::load(Path::new(SAVE_FILE)),
 			config: Config::new(),
 			infos: GameInfo::new(),
 		}
 	}
 
 	pub fn load_levels(&mut self) {
+		// Levels using an enemy type that couldn't be loaded are listed as invalid
+		match EnemyTypes::load(Path::new(ENEMY_DIR)) {
+			Ok(enemy_types) => self.enemy_types = Rc::new(enemy_types),
+			Err(err) => log::error!("Can't load enemy types: {err}"),
+		}
 		let level_dir: &Path = Path::new("./levels");
 		if !level_dir.exists() {
 			panic!("Levels directory doesn't exist");
 		}
-		for level in fs::read_dir(level_dir).unwrap() {
-			let path = level.unwrap().path();
-			if path.is_file() && path.extension().is_some_and(|ext| ext == "hbh") {
-				Level::level_parser(self, path.to_str().unwrap());
+		let mut paths: Vec<PathBuf> = fs::read_dir(level_dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().path())
+			.filter(|path| is_level_file(path))
+			.collect();
+		// The order of `read_dir` is platform dependent
+		paths.sort();
+		for path in paths {
+			let level = Level::load(&path, self.levels.len() as u32, &self.enemy_types)
+				.map_err(LevelError::Parse)
+				.and_then(|level| {
+					let report = level.validate();
+					if !report.errors.is_empty() {
+						return Err(LevelError::Events(report.errors));
+					}
+					log::debug!("{}: last event at {:?}", level.name, report.duration());
+					for warning in &report.warnings {
+						log::warn!("{}: {warning}", level.name);
+					}
+					Ok(level)
+				});
+			match level {
+				Ok(level) => self.levels.push(level),
+				Err(error) => {
+					// Broken levels are skipped but still listed in the menu
+					log::error!("{error}");
+					let file_name = path.file_name().unwrap().to_string_lossy();
+					let name = file_name.split('.').next().unwrap_or_default().to_string();
+					self.invalid_levels.push(InvalidLevel { name, error });
+				},
 			}
 		}
-		// Sort inversely by id
-		// TODO: Have better sorting function?
-		self.levels.sort_by_key(|x| u32::MAX - x.id);
+
+		let manifest = campaign::manifest_path(level_dir);
+		let campaign = match manifest.exists().then(|| Campaign::parse(&manifest)) {
+			Some(Ok(campaign)) => Some(campaign),
+			Some(Err(err)) => {
+				log::error!("Ignoring campaign manifest: {err}");
+				None
+			},
+			None => None,
+		};
+		match campaign {
+			Some(campaign) => campaign.order(&mut self.levels),
+			None => self
+				.levels
+				.sort_by_key(|level| level.meta.order.unwrap_or(i32::MAX)),
+		}
+		for (id, level) in self.levels.iter_mut().enumerate() {
+			level.id = id as u32;
+		}
+	}
+
+	pub fn is_unlocked(&self, level: &Level) -> bool {
+		self.progress.unlocks(level, &self.levels)
 	}
 
 	fn menu_key_handling(&mut self, key_state: &ElementState, key: &Key) {
@@ -263,14 +309,13 @@ impl Game {
 							_ => panic!("Invalid main menu choice"),
 						})
 					},
+					MenuChoice::Level(_) if self.levels.is_empty() => self.state,
 					MenuChoice::Level(id) => {
 						let new_id = (id + 1) % self.levels.len() as u16;
 						RunState::Menu(MenuChoice::Level(new_id))
 					},
 					MenuChoice::Resolution => {
-						let res_choice = &mut self.config.resolution_choice;
-						*res_choice = (*res_choice + 1) % DRAW_CONSTANTS.sizes.len() as u8;
-						self.window.request_window_resize(*res_choice);
+						self.cycle_resolution(1);
 						self.state
 					},
 					// Allow for future proofing
@@ -289,14 +334,14 @@ impl Game {
 							_ => panic!("Invalid main menu choice"),
 						})
 					},
+					MenuChoice::Level(_) if self.levels.is_empty() => self.state,
 					MenuChoice::Level(id) => {
-						let new_id = (id - 1) % self.levels.len() as u16;
+						let n_levels = self.levels.len() as u16;
+						let new_id = (id + n_levels - 1) % n_levels;
 						RunState::Menu(MenuChoice::Level(new_id))
 					},
 					MenuChoice::Resolution => {
-						let res_choice = &mut self.config.resolution_choice;
-						*res_choice = (*res_choice - 1) % DRAW_CONSTANTS.sizes.len() as u8;
-						self.window.request_window_resize(*res_choice);
+						self.cycle_resolution(-1);
 						self.state
 					},
 					// Allow for future proofing
@@ -310,6 +355,12 @@ impl Game {
 					MenuChoice::Play => RunState::Menu(MenuChoice::Level(0)),
 					MenuChoice::Options => RunState::Menu(MenuChoice::Resolution),
 					MenuChoice::Quit => RunState::Quitting,
+					// No valid level to start
+					MenuChoice::Level(_) if self.levels.is_empty() => self.state,
+					MenuChoice::Level(id) if !self.is_unlocked(&self.levels[id as usize]) => {
+						self.audio.play_sound(SoundBase::MenuBack);
+						self.state
+					},
 					MenuChoice::Level(id) => {
 						self.start_level(id as u32);
 						RunState::Playing
@@ -324,12 +375,147 @@ impl Game {
 		}
 	}
 
+	/// Selects the next (`step` 1) or previous (`step` -1) resolution
+	fn cycle_resolution(&mut self, step: i8) {
+		let n_sizes = DRAW_CONSTANTS.sizes.len() as i8;
+		let res_choice = &mut self.config.resolution_choice;
+		*res_choice = (*res_choice as i8 + step).rem_euclid(n_sizes) as u8;
+		self.window.request_window_resize(*res_choice);
+	}
+
+	fn pause_key_handling(&mut self, key_state: &ElementState, key: &Key) {
+		use winit::keyboard::NamedKey::*;
+		if key_state == &ElementState::Released {
+			return;
+		}
+		let pause_choice = match self.state {
+			RunState::Paused(choice) => choice,
+			_ => unreachable!("Not in pause state"),
+		};
+		const ENTRIES: [PauseChoice; 4] = [
+			PauseChoice::Resume,
+			PauseChoice::Restart,
+			PauseChoice::Options,
+			PauseChoice::Quit,
+		];
+		let moved = |step: usize| {
+			let index = ENTRIES
+				.iter()
+				.position(|entry| *entry == pause_choice)
+				.unwrap();
+			RunState::Paused(ENTRIES[(index + step) % ENTRIES.len()])
+		};
+		match key {
+			Key::Named(Escape) => {
+				self.audio.play_sound(SoundBase::MenuBack);
+				match pause_choice {
+					PauseChoice::Resolution => self.state = RunState::Paused(PauseChoice::Options),
+					_ => self.resume(),
+				}
+			},
+			Key::Named(ArrowDown) => {
+				self.audio.play_sound(SoundBase::MenuMove);
+				match pause_choice {
+					PauseChoice::Resolution => self.cycle_resolution(1),
+					_ => self.state = moved(1),
+				}
+			},
+			Key::Named(ArrowUp) => {
+				self.audio.play_sound(SoundBase::MenuMove);
+				match pause_choice {
+					PauseChoice::Resolution => self.cycle_resolution(-1),
+					_ => self.state = moved(ENTRIES.len() - 1),
+				}
+			},
+			Key::Named(Enter) => {
+				self.audio.play_sound(SoundBase::MenuSelect);
+				match pause_choice {
+					PauseChoice::Resume => self.resume(),
+					PauseChoice::Restart => self.restart_level(),
+					PauseChoice::Options => self.state = RunState::Paused(PauseChoice::Resolution),
+					PauseChoice::Resolution => self.state = RunState::Paused(PauseChoice::Options),
+					PauseChoice::Quit => self.quit_to_menu(),
+				}
+			},
+			_ => {},
+		}
+	}
+
+	/// Whether the level after the one played exists and is unlocked
+	pub fn next_level_available(&self) -> bool {
+		let Some(world) = &self.world else {
+			return false;
+		};
+		self
+			.levels
+			.get(world.level_id as usize + 1)
+			.is_some_and(|level| self.is_unlocked(level))
+	}
+
+	fn results_key_handling(&mut self, key_state: &ElementState, key: &Key) {
+		use winit::keyboard::NamedKey::*;
+		if key_state == &ElementState::Released {
+			return;
+		}
+		let (result_choice, with_choice): (_, fn(ResultChoice) -> RunState) = match self.state {
+			RunState::GameOver(choice) => (choice, RunState::GameOver),
+			RunState::Victory(choice) => (choice, RunState::Victory),
+			_ => unreachable!("Not in result state"),
+		};
+		// The next level is skipped when it can't be played
+		let entries: Vec<ResultChoice> = [
+			ResultChoice::Retry,
+			ResultChoice::NextLevel,
+			ResultChoice::LevelSelect,
+		]
+		.into_iter()
+		.filter(|entry| *entry != ResultChoice::NextLevel || self.next_level_available())
+		.collect();
+		let index = entries
+			.iter()
+			.position(|entry| *entry == result_choice)
+			.unwrap_or_default();
+		match key {
+			Key::Named(Escape) => {
+				self.audio.play_sound(SoundBase::MenuBack);
+				self.quit_to_level_select();
+			},
+			Key::Named(ArrowDown) => {
+				self.audio.play_sound(SoundBase::MenuMove);
+				self.state = with_choice(entries[(index + 1) % entries.len()]);
+			},
+			Key::Named(ArrowUp) => {
+				self.audio.play_sound(SoundBase::MenuMove);
+				self.state = with_choice(entries[(index + entries.len() - 1) % entries.len()]);
+			},
+			Key::Named(Enter) => {
+				self.audio.play_sound(SoundBase::MenuSelect);
+				match entries[index] {
+					ResultChoice::Retry => self.restart_level(),
+					ResultChoice::NextLevel => {
+						let next_id = self.world.as_ref().unwrap().level_id + 1;
+						self.start_level(next_id);
+						self.state = RunState::Playing;
+					},
+					ResultChoice::LevelSelect => self.quit_to_level_select(),
+				}
+			},
+			_ => {},
+		}
+	}
+
 	pub fn process_input(&mut self, key_state: &ElementState, key: &Key) {
 		use winit::keyboard::NamedKey::*;
 		// TODO: Some day, use data structures for keys
 
-		if matches!(self.state, RunState::Menu(_)) {
-			self.menu_key_handling(key_state, key);
+		match self.state {
+			RunState::Menu(_) => self.menu_key_handling(key_state, key),
+			RunState::Paused(_) => self.pause_key_handling(key_state, key),
+			RunState::GameOver(_) | RunState::Victory(_) => self.results_key_handling(key_state, key),
+			RunState::Playing if key_state == &ElementState::Pressed && key == &Key::Named(Escape) => {
+				self.pause()
+			},
+			_ => {},
 		}
 		match key {
 			Key::Named(ArrowUp) => self.inputs.up = matches!(key_state, ElementState::Pressed),
@@ -346,36 +532,177 @@ impl Game {
 	pub fn start_level(&mut self, id: u32) {
 		self.infos.start_level();
 		// The wolrd size is fixed as the lowest resolution and the graphics are scaled up
-		let new_world = World::start(
-			WORLD_SIZE,
-			self.levels.get(id as usize).unwrap().event_list.clone(),
-		);
+		let level = self.levels.get(id as usize).unwrap();
+		self.results = None;
+		self.recording = Some(Replay::new(level));
+		self.playback = None;
+		let new_world = World::start(WORLD_SIZE, level, self.enemy_types.clone());
+		if let Some(music) = &level.meta.music {
+			self.audio.play_music(music);
+		}
 		self.world = Some(new_world);
 	}
 
-	pub fn tick(&mut self, event_loop: &ActiveEventLoop) {
-		// TODO: Maybe better assignment of world?
-		// Applying events
-		{
-			let world = self.world.as_mut().unwrap();
-			world.process_events();
+	/// Plays the level of the replay with its inputs, returns whether the level was found
+	pub fn start_replay(&mut self, replay: Replay) -> bool {
+		let Some(level) = replay.find_level(&self.levels) else {
+			log::error!("Level '{}' of the replay isn't loaded", replay.level_file);
+			return false;
+		};
+		if replay.version != env!("CARGO_PKG_VERSION") {
+			log::warn!(
+				"Replay recorded by version {}, it may play differently",
+				replay.version
+			);
+		}
+		self.start_level(level.id);
+		self.recording = None;
+		self.playback = Some(ReplayPlayer::new(replay));
+		self.state = RunState::Playing;
+		true
+	}
+
+	/// Saves the inputs recorded since the beginning of the level, replays aren't saved again
+	fn save_replay(&mut self) {
+		let Some(recording) = self.recording.take() else {
+			return;
+		};
+		if recording.inputs.is_empty() {
+			return;
+		}
+		let stem = recording.level_file.split('.').next().unwrap_or_default();
+		let secs = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs();
+		let path = Path::new(REPLAY_DIR).join(format!("{stem}-{secs}.hbr"));
+		match recording.save(&path) {
+			Ok(()) => log::info!("Replay saved to {}", path.display()),
+			Err(err) => log::error!("Can't save replay {}: {err}", path.display()),
+		}
+	}
+
+	/// Stops the simulation and the sounds and opens the pause menu
+	pub fn pause(&mut self) {
+		if self.state != RunState::Playing {
+			return;
 		}
-		// Projectiles physics
-		self.update_projectiles();
-		// Main physics calculations
-		self.update_entities();
-		// Checks end condition
-		{
-			let world = self.world.as_mut().unwrap();
-			world.check_end(event_loop);
+		self.world.as_mut().unwrap().clock.pause();
+		self.audio.pause_all();
+		self.state = RunState::Paused(PauseChoice::Resume);
+	}
+
+	pub fn resume(&mut self) {
+		self.world.as_mut().unwrap().clock.resume();
+		self.audio.resume_all();
+		self.state = RunState::Playing;
+	}
+
+	/// Starts the level being played again from the beginning
+	pub fn restart_level(&mut self) {
+		self.save_replay();
+		let level_id = self.world.as_ref().unwrap().level_id;
+		self.audio.stop_sound_by_type(SoundBase::GameMusic);
+		self.start_level(level_id);
+		self.audio.resume_all();
+		self.state = RunState::Playing;
+	}
+
+	/// Leaves the level being played for the main menu
+	pub fn quit_to_menu(&mut self) {
+		self.save_replay();
+		self.playback = None;
+		self.world = None;
+		self.audio.stop_sound_by_type(SoundBase::GameMusic);
+		self.audio.resume_all();
+		self.state = RunState::Menu(MenuChoice::Play);
+	}
+
+	/// Leaves the level that ended for the level selection, on the level
+	pub fn quit_to_level_select(&mut self) {
+		let level_id = self.world.as_ref().map_or(0, |world| world.level_id);
+		self.playback = None;
+		self.world = None;
+		self.results = None;
+		self.audio.stop_sound_by_type(SoundBase::GameMusic);
+		self.state = RunState::Menu(MenuChoice::Level(level_id as u16));
+	}
+
+	/// Runs the ticks the clock of the world gives for the frame time
+	pub fn update(&mut self) {
+		let ticks = self.world.as_mut().unwrap().clock.frame(self.infos.dt);
+		for _ in 0..ticks {
+			// The level ended during one of the ticks
+			if self.state != RunState::Playing {
+				break;
+			}
+			self.tick();
+		}
+	}
+
+	/// Advances the world by one tick
+	pub fn tick(&mut self) {
+		if let Some(playback) = &mut self.playback {
+			match playback.next_inputs() {
+				Some(inputs) => self.inputs = inputs,
+				// The keyboard is used again once the replay ended
+				None => {
+					log::info!("Replay ended");
+					self.playback = None;
+					self.inputs = Inputs::new();
+				},
+			}
+		}
+		if let Some(recording) = &mut self.recording {
+			recording.record(&self.inputs);
 		}
+		let world = self.world.as_mut().unwrap();
+		let end = world.tick(&self.inputs);
+		for sound in world.sounds.drain(..) {
+			self.audio.play_sound(sound);
+		}
+		if let Some(end) = end {
+			self.finish_level(end);
+		}
+	}
+
+	/// Stops the level and shows its results
+	fn finish_level(&mut self, end: LevelEnd) {
+		let world = self.world.as_mut().unwrap();
+		world.clock.pause();
+		log::info!("{} ended: {end:?}, score {}", world.level_name, world.score);
+		let file = &self.levels[world.level_id as usize].file;
+		// Replays aren't recorded, even once the keyboard took over at their end
+		let replay = self.recording.is_none();
+		let best_score = self.progress.record_run(file, end, world.score, replay);
+		if end == LevelEnd::Cleared && !replay {
+			self.progress.save(Path::new(SAVE_FILE));
+		}
+		self.results = Some(LevelResults {
+			end,
+			score: world.score,
+			time: world.clock.elapsed(),
+			shots_fired: world.shots_fired,
+			shots_hit: world.shots_hit,
+			lives: world.player.hp,
+			best_score,
+		});
+		self.audio.stop_sound_by_type(SoundBase::GameMusic);
+		self.save_replay();
+		self.state = match end {
+			LevelEnd::Cleared if self.next_level_available() => {
+				RunState::Victory(ResultChoice::NextLevel)
+			},
+			LevelEnd::Cleared => RunState::Victory(ResultChoice::LevelSelect),
+			LevelEnd::Died | LevelEnd::TimeUp => RunState::GameOver(ResultChoice::Retry),
+		};
 	}
 
 	pub fn update_fps(&mut self) {
 		// Limit fps refresh for it to be readable
-		if self.infos.fps_cooldown.is_over() {
+		if self.infos.fps_refresh.elapsed() >= FPS_REFRESH {
 			self.infos.fps = (1. / self.infos.dt.as_secs_f64()).round() as u32;
-			self.infos.fps_cooldown.reset();
+			self.infos.fps_refresh = Instant::now();
 		}
 	}
 }
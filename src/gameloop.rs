@@ -2,15 +2,19 @@ use std::time::Instant;
 use winit::{
 	application::ApplicationHandler,
 	error::EventLoopError,
-	event::{ElementState, KeyEvent, WindowEvent},
+	event::{KeyEvent, WindowEvent},
 	event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-	keyboard::Key,
 };
 
-use crate::game::{Game, MenuChoice, RunState};
+use crate::{
+	game::{Game, RunState},
+	replay::Replay,
+};
 
 struct EventLoopState {
 	game_opt: Option<Game>,
+	/// Replay played as soon as the levels are loaded
+	replay: Option<Replay>,
 }
 
 impl ApplicationHandler for EventLoopState {
@@ -18,6 +22,9 @@ impl ApplicationHandler for EventLoopState {
 		if self.game_opt.is_none() {
 			let mut game = Game::launch(event_loop);
 			game.load_levels();
+			if let Some(replay) = self.replay.take() {
+				game.start_replay(replay);
+			}
 			self.game_opt = Some(game);
 		}
 	}
@@ -40,15 +47,10 @@ impl ApplicationHandler for EventLoopState {
 				game.resize(&size);
 			},
 
+			// The game is paused when the window loses focus
+			WindowEvent::Focused(false) => game.pause(),
+
 			WindowEvent::KeyboardInput { event: KeyEvent { ref logical_key, state, .. }, .. } => {
-				use winit::keyboard::NamedKey::*;
-				if matches!(state, ElementState::Pressed) {
-					// TODO: Move these into a function ???
-					if logical_key == &Key::Named(Escape) && game.state == RunState::Playing {
-						game.world = None;
-						game.state = RunState::Menu(MenuChoice::Play);
-					}
-				}
 				game.process_input(&state, logical_key);
 			},
 			_ => {},
@@ -64,7 +66,7 @@ impl ApplicationHandler for EventLoopState {
 		game.audio.delete_ended_sounds();
 		match game.state {
 			RunState::Playing => {
-				game.tick(event_loop);
+				game.update();
 
 				// Drawing
 				game.draw_in_game();
@@ -73,6 +75,22 @@ impl ApplicationHandler for EventLoopState {
 				game.redraw();
 				game.render();
 			},
+			RunState::Paused(choice) => {
+				game.draw_in_game();
+				game.draw_pause_menu(choice);
+
+				game.infos.update();
+				game.redraw();
+				game.render();
+			},
+			RunState::GameOver(choice) | RunState::Victory(choice) => {
+				game.draw_in_game();
+				game.draw_results(choice);
+
+				game.infos.update();
+				game.redraw();
+				game.render();
+			},
 			RunState::Menu(choice) => {
 				game.draw_menu(choice);
 
@@ -83,7 +101,6 @@ impl ApplicationHandler for EventLoopState {
 			RunState::Quitting => {
 				event_loop.exit();
 			},
-			_ => {},
 		}
 	}
 
@@ -95,9 +112,9 @@ impl ApplicationHandler for EventLoopState {
 	}
 }
 
-pub fn game_run() -> Result<(), EventLoopError> {
+pub fn game_run(replay: Option<Replay>) -> Result<(), EventLoopError> {
 	let event_loop = EventLoop::new()?;
 	event_loop.set_control_flow(ControlFlow::Poll);
-	let mut loop_state = EventLoopState { game_opt: None };
+	let mut loop_state = EventLoopState { game_opt: None, replay };
 	event_loop.run_app(&mut loop_state)
 }
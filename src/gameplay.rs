@@ -1,52 +1,73 @@
 use cgmath::{InnerSpace, Point2, Vector2, Zero};
-use std::{
-	collections::HashMap,
-	time::{Duration, Instant},
-};
-use winit::event_loop::ActiveEventLoop;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, rc::Rc, time::Duration};
 
 use crate::{
-	coords::{collide_rectangle, CenteredBox, Dimensions, RectF},
-	game::{Game, Inputs},
+	boss::{Boss, BossDef, BOSS_SCORE},
+	clock::{duration_to_ticks, SimClock, TICKS_PER_SEC},
+	coords::{Dimensions, RectF, Shape},
+	enemy::{EnemyDef, EnemyMovement, EnemyTypes},
+	game::Inputs,
+	grid::SpatialGrid,
+	level::{is_in_wave, Level, LevelMeta},
+	path::{EnemyPath, PathFollower},
+	pattern::{Emitter, PatternRunner},
+	projectiles::{Projectile, Projectiles},
 	sound::SoundBase,
 };
 
-pub const DT_60: f32 = 1. / 60.;
+/// Duration of a tick in seconds
+pub const DT_60: f32 = 1. / TICKS_PER_SEC as f32;
+/// Size of the cells of the grid of the projectiles, a few times the size of a projectile
+const GRID_CELL_SIZE: f32 = 32.;
+
+/// Timer counted in ticks of the clock of the world
 #[derive(Clone, Debug)]
 pub struct Cooldown {
-	last_emit: Option<Instant>,
-	cooldown: Duration,
+	last_emit: Option<u64>,
+	ticks: u64,
 }
 
 impl Cooldown {
 	/// Creates cooldown with secs second duration
 	pub fn with_secs(secs: f32) -> Self {
-		Cooldown { last_emit: None, cooldown: Duration::from_secs_f32(secs) }
+		Cooldown::with_duration(Duration::from_secs_f32(secs))
 	}
 
-	#[allow(dead_code)]
 	pub fn with_duration(value: Duration) -> Self {
-		Cooldown { last_emit: None, cooldown: value }
+		Cooldown::with_ticks(duration_to_ticks(value))
+	}
+
+	pub fn with_ticks(ticks: u64) -> Self {
+		Cooldown { last_emit: None, ticks }
 	}
 
-	pub fn is_over(&self) -> bool {
+	pub fn is_over(&self, clock: &SimClock) -> bool {
 		if let Some(last) = self.last_emit {
-			return Instant::elapsed(&last) >= self.cooldown;
+			return clock.since(last) >= self.ticks;
 		}
 		true
 	}
 
-	pub fn reset(&mut self) {
-		self.last_emit = Some(Instant::now());
+	pub fn reset(&mut self, clock: &SimClock) {
+		self.last_emit = Some(clock.now());
 	}
 }
 
+/// Movement of the player per tick on each axis
+pub const PLAYER_SPEED: f32 = 5.;
+/// Distance travelled by the shots of the player per tick
+pub const PLAYER_SHOT_SPEED: f32 = 10.;
+
 #[derive(Clone, Debug)]
 pub struct Player {
 	pub pos: Point2<f32>,
+	/// Position at the previous tick, for drawing between ticks
+	pub prev_pos: Point2<f32>,
 	vel: Vector2<f32>,
 	pub size: Dimensions<f32>,
-	pub hitbox: CenteredBox,
+	/// Much smaller than the sprite, drawn in its middle
+	pub hitbox: Shape,
 	pub hp: u32,
 	immunity: Cooldown,
 	new_shoot: Cooldown,
@@ -56,17 +77,18 @@ impl Player {
 	fn new() -> Self {
 		Self {
 			pos: (75., 200.).into(),
-			hitbox: CenteredBox { center: (75., 200.).into(), dims: (12., 12.).into() },
+			prev_pos: (75., 200.).into(),
+			hitbox: Shape::Circle { radius: 4. },
 			vel: (0., 0.).into(),
 			size: Dimensions { w: 48., h: 48. },
 			hp: 5,
-			immunity: Cooldown::with_secs(2.),
-			new_shoot: Cooldown::with_secs(15. * DT_60),
+			immunity: Cooldown::with_ticks(2 * TICKS_PER_SEC as u64),
+			new_shoot: Cooldown::with_ticks(15),
 		}
 	}
 
-	pub fn immunity_over(&self) -> bool {
-		self.immunity.is_over()
+	pub fn immunity_over(&self, clock: &SimClock) -> bool {
+		self.immunity.is_over(clock)
 	}
 
 	fn update_pos(&mut self, inputs: &Inputs, bounds: RectF, dt: f32) {
@@ -87,7 +109,7 @@ impl Player {
 
 		// Update pos
 		if self.vel != Vector2::zero() {
-			let new_pos = self.pos + 5. * self.vel * dt / DT_60;
+			let new_pos = self.pos + PLAYER_SPEED * self.vel * dt / DT_60;
 			// Separate x and y checks to allow movement while on an edge
 			if 0. <= new_pos.x && new_pos.x <= bounds.dims.w {
 				self.pos.x = new_pos.x;
@@ -95,21 +117,14 @@ impl Player {
 			if 0. <= new_pos.y && new_pos.y <= bounds.dims.h {
 				self.pos.y = new_pos.y;
 			}
-			self.hitbox.center = self.pos;
 		}
 	}
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum EnemyType {
-	Basic,
-	Sniper,
-}
-
 #[derive(Clone, Debug)]
 enum EnemyState {
 	NotSpawned,
-	OnScreen(fn(&mut Enemy, RectF)),
+	OnScreen,
 	OffScreen,
 	Dead,
 }
@@ -117,42 +132,53 @@ enum EnemyState {
 #[derive(Clone, Debug)]
 pub struct Enemy {
 	pub pos: Point2<f32>,
+	pub prev_pos: Point2<f32>,
 	vel: Vector2<f32>,
 	pub size: Dimensions<f32>,
 	pub hp: f32,
-	proj_cd: Cooldown,
-	pub variant: EnemyType,
+	/// Bullet pattern of the enemy, run while it is on screen
+	pattern: Option<PatternRunner>,
+	pub def: Rc<EnemyDef>,
 	state: EnemyState,
+	/// Path given in the level, enemies without one use the movement of their type
+	path: Option<PathFollower>,
+	/// Id of the event that spawned the enemy
+	spawned_by: u32,
+	/// Direction of the `Orbit` movement, chosen when the enemy enters the screen, `1.` turns
+	/// clockwise on the screen and `-1.` counterclockwise
+	orbit_dir: Option<f32>,
 }
 
 impl Enemy {
-	fn spawn(pos: Point2<f32>, variant: EnemyType) -> Enemy {
-		let (size, proj_cd) = match variant {
-			EnemyType::Basic => ((48., 48.).into(), Cooldown::with_secs(25. * DT_60)),
-			EnemyType::Sniper => ((32., 48.).into(), Cooldown::with_secs(40. * DT_60)),
-		};
+	pub fn spawn(
+		pos: Point2<f32>,
+		def: Rc<EnemyDef>,
+		path: Option<Rc<EnemyPath>>,
+		spawned_by: u32,
+	) -> Enemy {
 		Self {
 			pos,
+			prev_pos: pos,
 			vel: Vector2::zero(),
-			size,
-			hp: Self::max_hp(variant),
-			proj_cd,
-			variant,
+			size: def.size,
+			hp: def.hp,
+			pattern: def
+				.pattern
+				.as_ref()
+				.map(|pattern| PatternRunner::new(pattern.actions.clone(), true)),
+			def,
 			state: EnemyState::NotSpawned,
-		}
-	}
-
-	pub fn max_hp(variant: EnemyType) -> f32 {
-		match variant {
-			EnemyType::Basic => 15.,
-			EnemyType::Sniper => 8.,
+			path: path.map(|path| PathFollower::new(path, pos)),
+			spawned_by,
+			orbit_dir: None,
 		}
 	}
 
 	fn enemy_func(&mut self) -> fn(&mut Enemy, RectF) {
 		const SPEED: f32 = 0.5;
-		match self.variant {
-			EnemyType::Basic => |enemy, bounds| {
+		match self.def.movement {
+			EnemyMovement::Down => |enemy, _| enemy.vel = Vector2::unit_y() * SPEED,
+			EnemyMovement::Diverge => |enemy, bounds| {
 				enemy.vel = Vector2::unit_y() * SPEED;
 				if enemy.pos.x <= bounds.dims.w / 2. {
 					enemy.vel -= Vector2::unit_x() * SPEED;
@@ -160,28 +186,51 @@ impl Enemy {
 					enemy.vel += Vector2::unit_x() * SPEED;
 				}
 			},
-			EnemyType::Sniper => |enemy, bounds| {
+			EnemyMovement::Orbit => |enemy, bounds| {
 				let mid_up: Point2<f32> = (bounds.dims.w / 2., 0.).into();
 				let to_mid = (mid_up - enemy.pos).normalize();
-				// Orthogonal, needs better solution because only one direction works
-				enemy.vel = Vector2::new(to_mid.y, -to_mid.x) * SPEED * 5.;
+				// Enemies turn down into the screen, to the left on the right side and to the right
+				// on the left side
+				let dir = *enemy
+					.orbit_dir
+					.get_or_insert(if enemy.pos.x < mid_up.x { -1. } else { 1. });
+				enemy.vel = Vector2::new(to_mid.y, -to_mid.x) * dir * SPEED * 5.;
 			},
 		}
 	}
 
 	fn update_pos(&mut self, bounds: RectF, dt: f32) {
+		// Enemies with a path follow it from their spawn position, even outside of the screen, and
+		// leave once it is done and they are out of the screen
+		if let Some(path) = &mut self.path {
+			path.advance(&mut self.pos, dt);
+			self.vel = path.vel;
+			let on_screen = bounds.contains(self.pos);
+			match self.state {
+				EnemyState::NotSpawned | EnemyState::OnScreen if path.finished() && !on_screen => {
+					self.state = EnemyState::OffScreen
+				},
+				EnemyState::NotSpawned if on_screen => self.state = EnemyState::OnScreen,
+				_ => {},
+			}
+			return;
+		}
 		// Enemies behavior
 		const SPEED: f32 = 0.5;
 		match self.state {
 			EnemyState::NotSpawned => {
 				self.vel = Vector2::unit_y() * SPEED;
 				self.pos += self.vel * dt / DT_60;
+				// Going down, the enemy only enters the screen through its top
+				let entry = Point2::new(self.pos.x, self.pos.y.max(bounds.top_left.y));
 				if bounds.contains(self.pos) {
-					self.state = EnemyState::OnScreen(self.enemy_func());
-				};
+					self.state = EnemyState::OnScreen;
+				} else if !bounds.contains(entry) {
+					self.state = EnemyState::OffScreen;
+				}
 			},
-			EnemyState::OnScreen(f) => {
-				f(self, bounds);
+			EnemyState::OnScreen => {
+				self.enemy_func()(self, bounds);
 				if !bounds.contains(self.pos) {
 					self.state = EnemyState::OffScreen;
 				}
@@ -202,24 +251,41 @@ impl Enemy {
 	}
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum ProjType {
 	Basic,
 	Aimed,
 	PlayerShoot,
 }
 
-const PROJ_SIZE: Dimensions<f32> = Dimensions { w: 10., h: 10. };
-#[derive(Clone, Debug)]
-pub struct Projectile {
-	pub pos: Point2<f32>,
-	vel: Vector2<f32>,
-	pub variant: ProjType,
-}
+/// Size of the projectile sprites, the hitboxes of the shots of the player are taller
+pub const PROJ_SIZE: Dimensions<f32> = Dimensions { w: 10., h: 10. };
+
+impl ProjType {
+	pub const ALL: [ProjType; 3] = [ProjType::Basic, ProjType::Aimed, ProjType::PlayerShoot];
+
+	/// Size of the smallest box containing the hitboxes of all the projectiles
+	pub fn max_hitbox_extents() -> Dimensions<f32> {
+		ProjType::ALL
+			.iter()
+			.map(|variant| variant.hitbox().extents())
+			.fold(Dimensions { w: 0., h: 0. }, |max, extents| Dimensions {
+				w: max.w.max(extents.w),
+				h: max.h.max(extents.h),
+			})
+	}
 
-impl Projectile {
-	fn damage(&self) -> f32 {
-		match self.variant {
+	/// Bullets collide by their core, the shots of the player are long
+	pub fn hitbox(self) -> Shape {
+		match self {
+			ProjType::Basic => Shape::Circle { radius: 4. },
+			ProjType::Aimed => Shape::Circle { radius: 3. },
+			ProjType::PlayerShoot => Shape::Capsule { half: Vector2::new(0., 2.), radius: 4. },
+		}
+	}
+
+	fn damage(self) -> f32 {
+		match self {
 			ProjType::Basic => 1.,
 			ProjType::Aimed => 1.,
 			ProjType::PlayerShoot => 2.,
@@ -227,206 +293,618 @@ impl Projectile {
 	}
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EventType {
-	_SpawnEnemy(Point2<f32>, EnemyType),
-	_SpawnBoss(Point2<f32>),
+	#[serde(rename = "SpawnEnemy")]
+	/// Position, name of the enemy type and path
+	_SpawnEnemy(Point2<f32>, String, Option<Rc<EnemyPath>>),
+	#[serde(rename = "SpawnBoss")]
+	_SpawnBoss(Point2<f32>, BossDef),
 }
 
-#[derive(Clone, Debug)]
+/// What an event waits for before its delay starts
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+	/// Another event fired, `None` is the beginning of the level
+	Event(Option<u32>),
+	/// Every enemy spawned by the events of the wave is dead or gone, the wave is the event with
+	/// this label or the events of the block with this label
+	WaveCleared(String),
+	/// The score reached this value
+	Score(u64),
+	/// The player took damage since the beginning of the level
+	PlayerHit,
+	/// Less than this number of enemies, bosses included, are alive, once the first event fired or
+	/// if no event is scheduled
+	EnemiesBelow(usize),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Event {
+	/// Index of the event in the level file
+	#[serde(skip)]
 	pub id: u32,
-	pub time: Option<Instant>,
-	/// (`id`, `offset`), id of the trigger event, and the duration of the wait after said event is triggered
-	pub ref_evt: Option<(u32, Duration)>,
+	/// Optional name given in the level file, can be used instead of `id` to reference the event
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub label: Option<String>,
+	/// Tick at which the event fires, once its trigger happened
+	#[serde(skip)]
+	pub time: Option<u64>,
+	/// (`trigger`, `offset`), what the event waits for, and the duration of the wait after it happens
+	///
+	/// `None` once the trigger happened, the events of RON and JSON levels without one wait for the
+	/// beginning of the level.
+	pub trigger: Option<(Trigger, Duration)>,
 	pub variant: EventType,
 }
 
+impl Event {
+	/// Whether the event is part of the wave with this label
+	pub fn in_wave(&self, wave: &str) -> bool {
+		self
+			.label
+			.as_deref()
+			.is_some_and(|label| is_in_wave(label, wave))
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct EventSystem {
 	list: Vec<Event>,
-	history: HashMap<u32, Instant>,
+	/// Tick at which each event fired
+	history: HashMap<u32, u64>,
+	/// Ids of the events of each wave waited for by a `WaveCleared` trigger
+	waves: HashMap<String, Vec<u32>>,
 	_latest_id: u32,
 }
 
 impl EventSystem {
-	fn new(evt_list: Vec<Event>) -> Self {
-		use crate::game::LEVEL_REF;
+	fn new(evt_list: Vec<Event>, clock: &SimClock) -> Self {
+		let mut waves = HashMap::new();
+		for evt in evt_list.iter() {
+			if let Some((Trigger::WaveCleared(wave), _)) = &evt.trigger {
+				let ids = evt_list
+					.iter()
+					.filter(|e| e.in_wave(wave))
+					.map(|e| e.id)
+					.collect();
+				waves.insert(wave.clone(), ids);
+			}
+		}
 		let mut list = vec![];
 		for evt in evt_list {
 			let mut evt = evt.clone();
-			if evt.ref_evt.is_some_and(|(x, _)| x == LEVEL_REF) {
-				evt.time = Some(Instant::now() + evt.ref_evt.unwrap().1);
-				evt.ref_evt = None;
+			if let Some((Trigger::Event(None), t)) = evt.trigger {
+				evt.time = Some(clock.now() + duration_to_ticks(t));
+				evt.trigger = None;
 			}
 			list.push(evt);
 		}
-		Self { list, history: HashMap::new(), _latest_id: 0 }
+		Self { list, history: HashMap::new(), waves, _latest_id: 0 }
 	}
 
-	fn events_clear(&self) -> bool {
-		self.list.is_empty()
+	/// No event is scheduled anymore, and the remaining ones can't fire without enemies, except the
+	/// ones waiting for the player to be hit while enemy bullets are flying and the ones waiting for
+	/// enemies to be gone
+	fn events_clear(&self, enemy_bullets: bool) -> bool {
+		self.list.iter().all(|e| {
+			e.time.is_none()
+				&& match e.trigger {
+					Some((Trigger::PlayerHit, _)) => !enemy_bullets,
+					Some((Trigger::EnemiesBelow(_), _)) => false,
+					_ => true,
+				}
+		})
 	}
 }
 
+/// How a level ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelEnd {
+	Cleared,
+	Died,
+	TimeUp,
+}
+
 #[derive(Clone, Debug)]
 pub struct World {
+	/// Id of the level being played
+	pub level_id: u32,
 	pub player: Player,
-	pub projectiles: Vec<Projectile>,
+	pub projectiles: Projectiles,
 	pub enemies: Vec<Enemy>,
-	boundaries: RectF,
+	pub bosses: Vec<Boss>,
+	/// Definitions of the enemies spawned by the level
+	enemy_types: Rc<EnemyTypes>,
+	/// Play area, the player can't leave it and projectiles outside of it are removed
+	pub boundaries: RectF,
 	pub score: u64,
 	event_syst: EventSystem,
+	/// Number of times the player took damage
+	pub player_hits: u32,
+	/// Shots of the player, and the ones that hit an enemy or a boss
+	pub shots_fired: u32,
+	pub shots_hit: u32,
+	/// Sounds to play after the tick, the world doesn't own the audio
+	pub sounds: Vec<SoundBase>,
+	pub level_name: Rc<String>,
+	pub level_meta: Rc<LevelMeta>,
+	/// Time of the level, read by all the timers of the world
+	pub clock: SimClock,
+	/// Projectiles by position, rebuilt each tick for the collisions
+	grid: SpatialGrid,
 }
 
 impl World {
 	/// Create a new `World` instance that can draw a moving box.
-	pub fn start(dims: Dimensions<f32>, evt_list: Vec<Event>) -> Self {
+	pub fn start(dims: Dimensions<f32>, level: &Level, enemy_types: Rc<EnemyTypes>) -> Self {
+		let clock = SimClock::new();
+		let boundaries = dims.into_rect();
 		Self {
+			level_id: level.id,
 			player: Player::new(),
-			projectiles: Vec::new(),
+			projectiles: Projectiles::default(),
 			enemies: vec![],
-			boundaries: dims.into_rect(),
+			bosses: vec![],
+			enemy_types,
+			boundaries,
 			score: 0,
-			event_syst: EventSystem::new(evt_list),
+			event_syst: EventSystem::new(level.event_list.clone(), &clock),
+			player_hits: 0,
+			shots_fired: 0,
+			shots_hit: 0,
+			sounds: vec![],
+			level_name: level.name.clone(),
+			level_meta: level.meta.clone(),
+			clock,
+			grid: SpatialGrid::new(boundaries, GRID_CELL_SIZE),
 		}
 	}
 
-	pub fn check_end(&self, event_loop: &ActiveEventLoop) {
+	/// Time before the time limit of the level is reached, if it has one
+	pub fn time_left(&self) -> Option<Duration> {
+		let time_limit = self.level_meta.time_limit?;
+		Some(time_limit.saturating_sub(self.clock.elapsed()))
+	}
+
+	pub fn check_end(&self) -> Option<LevelEnd> {
 		if self.player.hp == 0 {
-			// Goofiest dead message
-			println!("Ur so dead 💀, RIP BOZO 🔫🔫😂😂😂😂");
-			event_loop.exit();
+			return Some(LevelEnd::Died);
 		}
-		if self.enemies.is_empty() && self.event_syst.events_clear() {
-			println!("You won! Score: {score}", score = self.score);
-			event_loop.exit();
+		if self.time_left().is_some_and(|t| t.is_zero()) {
+			return Some(LevelEnd::TimeUp);
 		}
+		// Without enemies, the conditions of the remaining events can't happen anymore, these events
+		// are cancelled
+		let enemy_bullets = self
+			.projectiles
+			.slots()
+			.any(|slot| !matches!(self.projectiles.variant[slot], ProjType::PlayerShoot));
+		if self.enemies.is_empty()
+			&& self.bosses.is_empty()
+			&& self.event_syst.events_clear(enemy_bullets)
+		{
+			return Some(LevelEnd::Cleared);
+		}
+		None
+	}
+
+	/// Positions at the beginning of the tick, the drawing goes from them to the new ones
+	pub fn store_positions(&mut self) {
+		self.player.prev_pos = self.player.pos;
+		for enemy in self.enemies.iter_mut() {
+			enemy.prev_pos = enemy.pos;
+		}
+		for boss in self.bosses.iter_mut() {
+			boss.prev_pos = boss.pos;
+		}
+		self.projectiles.store_positions();
 	}
 
 	pub fn process_events(&mut self) {
+		let now = self.clock.now();
 		let evt_list = &mut self.event_syst.list;
 		let map = &mut self.event_syst.history;
 		// Checks if absolute events are triggered
 		evt_list.retain(|e| {
-			if !e.time.is_some_and(|t| Instant::now() >= t) {
+			if e.time.is_none_or(|t| now < t) {
 				return true;
 			}
 			match &e.variant {
-				EventType::_SpawnEnemy(pos, variant) => {
-					self.enemies.push(Enemy::spawn(*pos, *variant));
+				EventType::_SpawnEnemy(pos, name, path) => match self.enemy_types.get(name) {
+					Some(def) => {
+						self
+							.enemies
+							.push(Enemy::spawn(*pos, def.clone(), path.clone(), e.id));
+					},
+					None => log::error!("Unknown enemy type '{name}', event {} is skipped", e.id),
 				},
-				var => {
-					unimplemented!("Event variant '{var:?}' not implemented")
+				EventType::_SpawnBoss(pos, def) => {
+					self
+						.bosses
+						.push(Boss::spawn(*pos, def.clone(), e.id, &self.clock));
 				},
 			}
-			map.insert(e.id, Instant::now());
+			match &e.label {
+				Some(label) => log::debug!("Event #{label} ({}) triggered", e.id),
+				None => log::debug!("Event {} triggered", e.id),
+			}
+			map.insert(e.id, now);
 			false
 		});
-		// Updates relative events to be transformed into absolute events
-		for e in evt_list.iter_mut() {
-			if let Some((id, t)) = e.ref_evt {
-				if map.contains_key(&id) {
-					e.ref_evt = None;
-					e.time = Some(map[&id] + t);
-				}
+		self.resolve_triggers();
+	}
+
+	/// Updates relative events to be transformed into absolute events once their trigger happens
+	fn resolve_triggers(&mut self) {
+		let now = self.clock.now();
+		let map = &self.event_syst.history;
+		let waves = &self.event_syst.waves;
+		// No enemy is alive before the first one spawns, unless no spawn is coming
+		let started = !map.is_empty() || self.event_syst.list.iter().all(|e| e.time.is_none());
+		for e in self.event_syst.list.iter_mut() {
+			let Some((trigger, t)) = &e.trigger else {
+				continue;
+			};
+			let start = match trigger {
+				Trigger::Event(Some(id)) => map.get(id).copied(),
+				Trigger::Event(None) => Some(0),
+				Trigger::WaveCleared(wave) => {
+					let wave = &waves[wave];
+					let cleared = wave.iter().all(|id| map.contains_key(id))
+						&& !self
+							.enemies
+							.iter()
+							.any(|enemy| wave.contains(&enemy.spawned_by))
+						&& !self
+							.bosses
+							.iter()
+							.any(|boss| wave.contains(&boss.spawned_by));
+					cleared.then_some(now)
+				},
+				Trigger::Score(score) => (self.score >= *score).then_some(now),
+				Trigger::PlayerHit => (self.player_hits > 0).then_some(now),
+				Trigger::EnemiesBelow(n) => {
+					(started && self.enemies.len() + self.bosses.len() < *n).then_some(now)
+				},
+			};
+			if let Some(start) = start {
+				e.time = Some(start + duration_to_ticks(*t));
+				e.trigger = None;
 			}
 		}
 	}
-}
 
-impl Game {
-	pub fn update_entities(&mut self) {
-		let world = &mut self.world.as_mut().unwrap();
-		let dt = self.infos.dt;
-		let inputs = &self.inputs;
+	/// Advances the world by one tick, returns how the level ended if it did
+	pub fn tick(&mut self, inputs: &Inputs) -> Option<LevelEnd> {
+		self.store_positions();
+		self.process_events();
+		// Projectiles physics
+		self.update_projectiles();
+		// Main physics calculations
+		self.update_entities(inputs);
+		self.clock.advance();
+		// The last enemies of a wave may have died during the tick, the level isn't cleared if an
+		// event waits for it
+		self.resolve_triggers();
+		self.check_end()
+	}
+
+	fn update_entities(&mut self, inputs: &Inputs) {
+		let clock = &self.clock;
 		// Player
-		let player = &mut world.player;
-		player.update_pos(inputs, world.boundaries, dt.as_secs_f32());
+		let player = &mut self.player;
+		player.update_pos(inputs, self.boundaries, DT_60);
 		// Player shoot
-		if inputs.shoot & player.new_shoot.is_over() {
-			let proj = Projectile {
-				pos: player.pos - player.size.h / 2. * Vector2::unit_y(),
-				vel: Vector2::unit_y() * -10.,
-				variant: ProjType::PlayerShoot,
-			};
-			world.projectiles.push(proj);
-			self.audio.play_sound(SoundBase::PlayerShoot);
-			player.new_shoot.reset();
+		if inputs.shoot & player.new_shoot.is_over(clock) {
+			let proj = Projectile::new(
+				player.pos - player.size.h / 2. * Vector2::unit_y(),
+				Vector2::unit_y() * -PLAYER_SHOT_SPEED,
+				ProjType::PlayerShoot,
+			);
+			self.projectiles.push(proj);
+			self.shots_fired += 1;
+			self.sounds.push(SoundBase::PlayerShoot);
+			player.new_shoot.reset(clock);
 		}
 
 		// Enemies physics
 		// Updates position
-		world.enemies.retain_mut(|enemy| {
-			enemy.update_pos(world.boundaries, dt.as_secs_f32());
+		self.enemies.retain_mut(|enemy| {
+			enemy.update_pos(self.boundaries, DT_60);
 			// If the enemy is dead, add points
 			if matches!(enemy.state, EnemyState::Dead) {
-				world.score += 100;
+				self.score += enemy.def.score;
 				return false;
 			}
 			// Removes if offscreen
 			!matches!(enemy.state, EnemyState::OffScreen)
 		});
-		for enemy in world.enemies.iter_mut() {
+		// Bosses
+		self.bosses.retain_mut(|boss| {
+			let projectiles = boss.update(self.boundaries, player.pos, clock, DT_60);
+			self.projectiles.extend(projectiles);
+			if boss.is_dead() {
+				log::debug!("Boss {} defeated", boss.def.name);
+				self.score += BOSS_SCORE;
+				return false;
+			}
+			true
+		});
+		for enemy in self.enemies.iter_mut() {
 			// Shooting
-			if enemy.proj_cd.is_over() && world.boundaries.contains(enemy.pos) {
-				let proj = {
-					let pos = enemy.pos + enemy.size.h * 0.6 * Vector2::unit_y();
-					match enemy.variant {
-						EnemyType::Basic => {
-							Projectile { pos, vel: Vector2::unit_y() * 10., variant: ProjType::Basic }
-						},
-						EnemyType::Sniper => {
-							let delta = player.pos - pos;
-							let mut to_player = Vector2::zero();
-							if delta != Vector2::zero() {
-								to_player = delta.normalize();
-							}
-							Projectile { pos, vel: 10. * to_player, variant: ProjType::Aimed }
-						},
-					}
-				};
-				world.projectiles.push(proj);
-				enemy.proj_cd.reset();
+			let (Some(pattern), Some(runner)) = (&enemy.def.pattern, &mut enemy.pattern) else {
+				continue;
+			};
+			if self.boundaries.contains(enemy.pos) {
+				let pos = enemy.pos + enemy.size.h * 0.6 * Vector2::unit_y();
+				let mut emitter = Emitter { pos, dir: 0., speed: 0., vanished: false };
+				let shots = runner.update(&mut emitter, player.pos, DT_60);
+				self.projectiles.extend(
+					shots
+						.into_iter()
+						.map(|shot| Projectile::fired(pos, shot, pattern.variant)),
+				);
 			}
 		}
 	}
 
-	pub fn update_projectiles(&mut self) {
-		let world = &mut self.world.as_mut().unwrap();
-		let player = &mut world.player;
-
-		world.projectiles.retain_mut(|proj| {
-			proj.pos += proj.vel * self.infos.dt.as_secs_f32() / DT_60;
-			if !world.boundaries.contains(proj.pos) {
-				return false;
-			}
-
-			for enemy in world.enemies.iter_mut() {
-				if matches!(proj.variant, ProjType::PlayerShoot)
-					& collide_rectangle(enemy.pos, enemy.size, proj.pos, PROJ_SIZE)
+	fn update_projectiles(&mut self) {
+		let fired = self.projectiles.update(self.boundaries, self.player.pos);
+
+		// Each target only tests the projectiles of the cells around it, the box around the target
+		// is padded so that it contains the center of every projectile touching it
+		let padding = ProjType::max_hitbox_extents();
+		let projectiles = &mut self.projectiles;
+		let grid = &mut self.grid;
+		grid.rebuild(
+			projectiles
+				.slots()
+				.map(|slot| (slot, projectiles.pos[slot])),
+		);
+		// Damage of the player shots hitting a target, the shots are removed
+		let mut shot_by_player = |pos, hitbox: Shape| {
+			let mut damage = 0.;
+			grid.query(pos, hitbox.extents() + padding, |slot| {
+				let variant = projectiles.variant[slot];
+				if projectiles.is_alive(slot)
+					&& matches!(variant, ProjType::PlayerShoot)
+					&& hitbox.intersects(pos, &variant.hitbox(), projectiles.pos[slot])
 				{
-					enemy.get_shot(proj.damage());
-					return false;
+					damage += variant.damage();
+					self.shots_hit += 1;
+					projectiles.remove(slot);
 				}
+			});
+			damage
+		};
+		for enemy in self.enemies.iter_mut() {
+			let damage = shot_by_player(enemy.pos, enemy.def.hitbox);
+			if damage > 0. {
+				enemy.get_shot(damage);
+			}
+		}
+		for boss in self.bosses.iter_mut() {
+			let damage = shot_by_player(boss.pos, boss.hitbox);
+			if damage > 0. {
+				boss.get_shot(damage);
 			}
+		}
 
-			if player.immunity.is_over()
-				& !matches!(proj.variant, ProjType::PlayerShoot)
-				& collide_rectangle(player.pos, player.hitbox.dims, proj.pos, PROJ_SIZE)
-			{
-				if player.hp > 0 {
-					// Avoids underflow if damage is more than 1
-					player.hp = player.hp.saturating_sub(proj.damage() as u32)
+		let player = &mut self.player;
+		if player.immunity.is_over(&self.clock) {
+			// The first bullet touching the player hits it, the others miss during the immunity
+			let mut first = None;
+			grid.query(player.pos, player.hitbox.extents() + padding, |slot| {
+				let variant = projectiles.variant[slot];
+				if projectiles.is_alive(slot)
+					&& !matches!(variant, ProjType::PlayerShoot)
+					&& player
+						.hitbox
+						.intersects(player.pos, &variant.hitbox(), projectiles.pos[slot])
+				{
+					first = Some(first.map_or(slot, |first: usize| first.min(slot)));
 				}
-				if player.hp == 0 {
-					return false;
+			});
+			if let Some(slot) = first {
+				// Avoids underflow if damage is more than 1
+				player.hp = player
+					.hp
+					.saturating_sub(projectiles.variant[slot].damage() as u32);
+				self.player_hits += 1;
+				if player.hp > 0 {
+					player.immunity.reset(&self.clock);
 				}
+				projectiles.remove(slot);
+			}
+		}
 
-				player.immunity.reset();
-				return false;
+		projectiles.extend(fired);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::*;
+	use crate::{enemy::ENEMY_DIR, game::WORLD_SIZE};
+
+	/// Tick of the event with this id, `None` while it isn't scheduled
+	fn event_time(world: &World, id: u32) -> Option<u64> {
+		let evt = world.event_syst.list.iter().find(|evt| evt.id == id);
+		evt.and_then(|evt| evt.time)
+	}
+
+	fn start(content: &str) -> World {
+		let enemy_types = Rc::new(EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap());
+		let level = Level::parse_hbh(Path::new("levels/test.hbh"), content, 0, &enemy_types).unwrap();
+		World::start(WORLD_SIZE, &level, enemy_types)
+	}
+
+	#[test]
+	fn enemies_below_waits_for_the_first_spawn() {
+		let mut world =
+			start("@spawn-enemy basic 1 400 -25 -\n@spawn-enemy basic 0 400 -25 enemies-below:1");
+		for _ in 0..TICKS_PER_SEC {
+			assert_eq!(world.tick(&Inputs::default()), None);
+		}
+		assert_eq!(event_time(&world, 1), None);
+		// The enemy spawned, the event waits for it to be gone
+		world.tick(&Inputs::default());
+		assert_eq!(world.enemies.len(), 1);
+		assert_eq!(event_time(&world, 1), None);
+		world.enemies.clear();
+		world.tick(&Inputs::default());
+		assert!(event_time(&world, 1).is_some());
+	}
+
+	#[test]
+	fn lone_enemies_below_events_fire() {
+		let mut world = start("@spawn-enemy basic 0.5 400 -25 enemies-below:1");
+		// The level isn't cleared before the event fires
+		assert_eq!(world.tick(&Inputs::default()), None);
+		assert!(event_time(&world, 0).is_some());
+		for _ in 0..TICKS_PER_SEC / 2 {
+			assert_eq!(world.tick(&Inputs::default()), None);
+		}
+		assert_eq!(world.enemies.len(), 1);
+	}
+
+	#[test]
+	fn pending_enemies_below_events_arent_clear() {
+		let world = start("@spawn-enemy basic 0 400 -25 enemies-below:1");
+		assert!(!world.event_syst.events_clear(false));
+		let world = start("@spawn-enemy basic 0 400 -25 score:100");
+		assert!(world.event_syst.events_clear(false));
+	}
+
+	#[test]
+	fn hit_events_wait_for_the_enemy_bullets() {
+		let level = "@spawn-enemy basic 0 400 -25 hit";
+		let mut world = start(level);
+		// Far from the player
+		let pos = Point2::new(100., 100.);
+		world
+			.projectiles
+			.push(Projectile::new(pos, Vector2::zero(), ProjType::Basic));
+		assert_eq!(world.tick(&Inputs::default()), None);
+		world.projectiles = Projectiles::default();
+		assert_eq!(world.tick(&Inputs::default()), Some(LevelEnd::Cleared));
+	}
+
+	#[test]
+	fn score_events_are_cancelled_without_enemies() {
+		let mut world = start("@spawn-enemy basic 0 400 -25 score:100");
+		assert_eq!(world.tick(&Inputs::default()), Some(LevelEnd::Cleared));
+	}
+
+	/// Ticks until the level is cleared, the player doesn't move nor shoot
+	fn ticks_to_clear(world: &mut World, max_ticks: u32) -> Option<u32> {
+		(1..=max_ticks).find(|_| world.tick(&Inputs::default()) == Some(LevelEnd::Cleared))
+	}
+
+	#[test]
+	fn enemies_leave_at_the_end_of_their_path() {
+		// The path never reaches the screen
+		let mut world = start("@path stay {\n\t@hold 1\n}\n@spawn-enemy basic:stay 0 400 -25 -");
+		assert_eq!(ticks_to_clear(&mut world, 600), Some(TICKS_PER_SEC + 1));
+
+		// Going down, the enemy never reaches the screen either
+		let mut world = start("@spawn-enemy basic 0 400 2000 -");
+		assert_eq!(ticks_to_clear(&mut world, 600), Some(1));
+	}
+
+	#[test]
+	fn enemies_follow_their_path_out_of_the_screen_and_back() {
+		let mut world = start(
+			"@path detour {\n\
+				@line 0 100  600\n\
+				@line -500 100  600\n\
+				@line 0 300  600\n\
+				@line 0 1200  600\n\
+			}\n\
+			@spawn-enemy basic:detour 0 400 -25 -",
+		);
+		let mut left_screen = false;
+		let mut came_back = false;
+		for _ in 0..150 {
+			world.tick(&Inputs::default());
+			let enemy = &world.enemies[0];
+			if !world.boundaries.contains(enemy.pos) && enemy.pos.x < 0. {
+				left_screen = true;
+			} else if left_screen && world.boundaries.contains(enemy.pos) {
+				came_back = true;
 			}
-			true
+		}
+		assert!(left_screen && came_back);
+		assert!(ticks_to_clear(&mut world, 300).is_some());
+	}
+
+	#[test]
+	fn orbiting_enemies_turn_into_the_screen_on_both_sides() {
+		let def = Rc::new(EnemyDef {
+			name: "orbit".into(),
+			size: (32., 32.).into(),
+			hitbox: Shape::Circle { radius: 16. },
+			hp: 1.,
+			sprite: Point2::new(0, 0),
+			movement: EnemyMovement::Orbit,
+			pattern: None,
+			score: 0,
+		});
+		let bounds = WORLD_SIZE.into_rect();
+		for x in [200., WORLD_SIZE.w - 200.] {
+			let mut enemy = Enemy::spawn(Point2::new(x, 100.), def.clone(), None, 0);
+			let start = enemy.pos;
+			for _ in 0..60 {
+				enemy.update_pos(bounds, DT_60);
+			}
+			assert!(matches!(enemy.state, EnemyState::OnScreen), "{x}");
+			assert!(enemy.pos.y > start.y, "{x}");
+			// Around the top middle, towards the middle
+			assert!((enemy.pos.x - WORLD_SIZE.w / 2.).abs() < (x - WORLD_SIZE.w / 2.).abs());
+		}
+	}
+
+	#[test]
+	fn projectile_hitboxes_fit_in_the_padding() {
+		let padding = ProjType::max_hitbox_extents();
+		for variant in ProjType::ALL {
+			let extents = variant.hitbox().extents();
+			assert!(
+				extents.w <= padding.w && extents.h <= padding.h,
+				"{variant:?}"
+			);
+		}
+		assert_eq!((padding.w, padding.h), (8., 12.));
+	}
+
+	#[test]
+	fn shots_hit_from_the_next_cell() {
+		let mut world = start("");
+		let def = Rc::new(EnemyDef {
+			name: "target".into(),
+			size: (4., 4.).into(),
+			hitbox: Shape::Aabb((4., 4.).into()),
+			hp: 10.,
+			sprite: Point2::new(0, 0),
+			movement: EnemyMovement::Down,
+			pattern: None,
+			score: 0,
 		});
+		// The bottom of the target is 5.5 pixels above the row of cells of the shot, whose capsule
+		// reaches 6 pixels above its center
+		let bottom = GRID_CELL_SIZE * 2. - 5.5;
+		world
+			.enemies
+			.push(Enemy::spawn(Point2::new(16., bottom - 2.), def, None, 0));
+		let pos = Point2::new(16., GRID_CELL_SIZE * 2. + 0.2);
+		world
+			.projectiles
+			.push(Projectile::new(pos, Vector2::zero(), ProjType::PlayerShoot));
+		world.update_projectiles();
+		assert_eq!(world.shots_hit, 1);
+		assert!(world.enemies[0].hp < 10.);
 	}
 }
@@ -0,0 +1,142 @@
+use cgmath::Point2;
+
+use crate::coords::{Dimensions, RectF};
+
+/// Uniform grid over the play area, to find the points near a box without testing all of them
+///
+/// The grid is rebuilt each tick: the points are sorted by cell, so the points of a row of cells
+/// are contiguous and a query reads one slice per row.
+#[derive(Clone, Debug)]
+pub struct SpatialGrid {
+	bounds: RectF,
+	cell_size: f32,
+	cols: usize,
+	rows: usize,
+	/// Index in `items` of the first point of each cell, followed by the number of points
+	starts: Vec<u32>,
+	/// Indices of the points, sorted by cell
+	items: Vec<u32>,
+	/// Index and cell of each point, and where the next point of each cell goes, kept to reuse the
+	/// memory
+	cells: Vec<(u32, u32)>,
+	next: Vec<u32>,
+}
+
+impl SpatialGrid {
+	pub fn new(bounds: RectF, cell_size: f32) -> SpatialGrid {
+		let cols = (bounds.dims.w / cell_size).ceil().max(1.) as usize;
+		let rows = (bounds.dims.h / cell_size).ceil().max(1.) as usize;
+		SpatialGrid {
+			bounds,
+			cell_size,
+			cols,
+			rows,
+			starts: vec![0; cols * rows + 1],
+			items: vec![],
+			cells: vec![],
+			next: vec![],
+		}
+	}
+
+	/// Cell of a position, the positions outside of the grid are in the cells of its edges
+	fn cell_coords(&self, pos: Point2<f32>) -> (usize, usize) {
+		let x = (pos.x - self.bounds.top_left.x) / self.cell_size;
+		let y = (pos.y - self.bounds.top_left.y) / self.cell_size;
+		(
+			x.clamp(0., (self.cols - 1) as f32) as usize,
+			y.clamp(0., (self.rows - 1) as f32) as usize,
+		)
+	}
+
+	/// Replaces the points of the grid, the queries give back the index paired with each point
+	pub fn rebuild(&mut self, points: impl IntoIterator<Item = (usize, Point2<f32>)>) {
+		self.cells.clear();
+		self.starts.fill(0);
+		for (i, pos) in points {
+			let (x, y) = self.cell_coords(pos);
+			let cell = y * self.cols + x;
+			self.cells.push((i as u32, cell as u32));
+			self.starts[cell + 1] += 1;
+		}
+		for i in 1..self.starts.len() {
+			self.starts[i] += self.starts[i - 1];
+		}
+		self.next.clear();
+		self.next.extend_from_slice(&self.starts);
+		self.items.resize(self.cells.len(), 0);
+		for &(i, cell) in self.cells.iter() {
+			let next = &mut self.next[cell as usize];
+			self.items[*next as usize] = i;
+			*next += 1;
+		}
+	}
+
+	/// Calls `f` with the points in the cells overlapping the box, some of them may be outside of it
+	pub fn query(&self, center: Point2<f32>, dims: Dimensions<f32>, mut f: impl FnMut(usize)) {
+		let (left, top) = self.cell_coords((center.x - dims.w / 2., center.y - dims.h / 2.).into());
+		let (right, bottom) =
+			self.cell_coords((center.x + dims.w / 2., center.y + dims.h / 2.).into());
+		for y in top..=bottom {
+			let row = y * self.cols;
+			let start = self.starts[row + left] as usize;
+			let end = self.starts[row + right + 1] as usize;
+			for &i in &self.items[start..end] {
+				f(i as usize);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn grid() -> SpatialGrid {
+		let bounds = RectF { top_left: (-50., -50.).into(), dims: (200., 100.).into() };
+		SpatialGrid::new(bounds, 32.)
+	}
+
+	fn query(grid: &SpatialGrid, center: (f32, f32), dims: (f32, f32)) -> Vec<usize> {
+		let mut found = vec![];
+		grid.query(center.into(), dims.into(), |i| found.push(i));
+		found.sort();
+		found
+	}
+
+	#[test]
+	fn cells_cover_the_bounds() {
+		let grid = grid();
+		assert_eq!((grid.cols, grid.rows), (7, 4));
+		assert_eq!(grid.starts.len(), 7 * 4 + 1);
+		assert_eq!(grid.cell_coords((-50., -50.).into()), (0, 0));
+		assert_eq!(grid.cell_coords((149.9, 49.9).into()), (6, 3));
+		// Outside of the bounds, in the cells of the edges
+		assert_eq!(grid.cell_coords((-1000., 1000.).into()), (0, 3));
+		assert_eq!(grid.cell_coords((f32::INFINITY, -1000.).into()), (6, 0));
+	}
+
+	#[test]
+	fn finds_the_points_near_a_box() {
+		let mut grid = grid();
+		let points = [(0., 0.), (10., 5.), (100., 40.), (-45., -45.), (500., 500.)];
+		grid.rebuild(points.iter().enumerate().map(|(i, &pos)| (i, pos.into())));
+		assert_eq!(query(&grid, (5., 5.), (4., 4.)), [0, 1]);
+		assert_eq!(query(&grid, (100., 40.), (1., 1.)), [2]);
+		// The point out of the bounds is in the bottom right cell
+		assert_eq!(query(&grid, (140., 45.), (4., 4.)), [4]);
+		assert_eq!(query(&grid, (50., 0.), (400., 400.)), [0, 1, 2, 3, 4]);
+		assert_eq!(query(&grid, (-40., 40.), (4., 4.)), [] as [usize; 0]);
+	}
+
+	#[test]
+	fn rebuild_replaces_the_points() {
+		let mut grid = grid();
+		grid.rebuild((0..100).map(|i| (i, Point2::new(0., 0.))));
+		assert_eq!(query(&grid, (0., 0.), (1., 1.)).len(), 100);
+		grid.rebuild([(7, Point2::new(100., 0.))]);
+		assert_eq!(query(&grid, (0., 0.), (1., 1.)), [] as [usize; 0]);
+		assert_eq!(query(&grid, (100., 0.), (1., 1.)), [7]);
+		grid.rebuild([]);
+		assert_eq!(query(&grid, (50., 0.), (400., 400.)), [] as [usize; 0]);
+	}
+}
@@ -0,0 +1,128 @@
+use std::{fmt, rc::Rc, time::Duration};
+
+use crate::{
+	bot::Autopilot,
+	clock::{ticks_to_duration, TICKS_PER_SEC},
+	enemy::EnemyTypes,
+	game::{Inputs, WORLD_SIZE},
+	gameplay::{LevelEnd, World},
+	level::Level,
+	replay::{Replay, ReplayError, ReplayPlayer},
+};
+
+/// Longest run simulated without inputs or with the autopilot, in ticks, including on levels with
+/// a longer time limit
+pub const MAX_TICKS: u64 = 10 * 60 * TICKS_PER_SEC as u64;
+
+/// Result of a level simulated without window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOutcome {
+	/// `None` when the inputs ran out or `max_ticks` was reached before the end of the level
+	pub end: Option<LevelEnd>,
+	pub score: u64,
+	pub ticks: u64,
+	/// Number of times the player took damage
+	pub hits: u32,
+	pub lives: u32,
+}
+
+impl RunOutcome {
+	pub fn time(&self) -> Duration {
+		ticks_to_duration(self.ticks)
+	}
+}
+
+impl fmt::Display for RunOutcome {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let end = match self.end {
+			Some(LevelEnd::Cleared) => "cleared",
+			Some(LevelEnd::Died) => "died",
+			Some(LevelEnd::TimeUp) => "time up",
+			None => "unfinished",
+		};
+		write!(
+			f,
+			"{end} after {} ticks ({:.2}s), score {}, {} hit(s), {} lives left",
+			self.ticks,
+			self.time().as_secs_f32(),
+			self.score,
+			self.hits,
+			self.lives,
+		)
+	}
+}
+
+/// Plays the level with the inputs given for each tick by `next_inputs`, without window, pixels
+/// surface or audio
+///
+/// The run stops when the level ends, when `next_inputs` returns `None` or after `max_ticks`.
+pub fn run_level(
+	level: &Level,
+	enemy_types: Rc<EnemyTypes>,
+	max_ticks: u64,
+	mut next_inputs: impl FnMut(&World) -> Option<Inputs>,
+) -> RunOutcome {
+	let mut world = World::start(WORLD_SIZE, level, enemy_types);
+	let mut end = None;
+	while end.is_none() && world.clock.now() < max_ticks {
+		let Some(inputs) = next_inputs(&world) else {
+			break;
+		};
+		end = world.tick(&inputs);
+		world.sounds.clear();
+	}
+	RunOutcome {
+		end,
+		score: world.score,
+		ticks: world.clock.now(),
+		hits: world.player_hits,
+		lives: world.player.hp,
+	}
+}
+
+/// Plays the level with the autopilot
+pub fn run_autopilot(level: &Level, enemy_types: Rc<EnemyTypes>, max_ticks: u64) -> RunOutcome {
+	let mut autopilot = Autopilot::new();
+	run_level(level, enemy_types, max_ticks, |world| {
+		Some(autopilot.inputs(world))
+	})
+}
+
+/// Plays the level with the inputs of the replay, which must have been recorded on this level
+pub fn run_replay(
+	level: &Level,
+	enemy_types: Rc<EnemyTypes>,
+	replay: Replay,
+) -> Result<RunOutcome, ReplayError> {
+	if replay.level_file != level.file {
+		return Err(ReplayError::WrongLevel { replay: replay.level_file, level: level.file.clone() });
+	}
+	let max_ticks = replay.inputs.len() as u64;
+	let mut player = ReplayPlayer::new(replay);
+	Ok(run_level(level, enemy_types, max_ticks, |_| {
+		player.next_inputs()
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::*;
+	use crate::enemy::ENEMY_DIR;
+
+	#[test]
+	fn replays_play_on_their_level() {
+		let enemy_types = Rc::new(EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap());
+		let load = |file: &str| Level::load(Path::new(file), 0, &enemy_types).unwrap();
+		let (level1, level2) = (load("levels/level1.hbh"), load("levels/level2.hbh"));
+		let mut replay = Replay::new(&level1);
+		(0..60).for_each(|_| replay.record(&Inputs::default()));
+		let outcome = run_replay(&level1, enemy_types.clone(), replay.clone()).unwrap();
+		assert_eq!((outcome.end, outcome.ticks), (None, 60));
+		assert!(matches!(
+			run_replay(&level2, enemy_types.clone(), replay),
+			Err(ReplayError::WrongLevel { .. })
+		));
+	}
+}
@@ -0,0 +1,2620 @@
+use cgmath::{Point2, Vector2, Zero};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt, fs,
+	path::{Path, PathBuf},
+	rc::Rc,
+	str::FromStr,
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	boss::{BossDef, BossMovement, BossPhase},
+	campaign::UnlockCondition,
+	coords::Shape,
+	draw::BACKGROUND_PALETTE,
+	enemy::{format_shape, parse_pattern, parse_shape, EnemyTypes, ShotPattern},
+	gameplay::{Event, EventType, Trigger},
+	path::{EnemyPath, PathSegment},
+};
+
+/// Longest time written in a level, one day
+pub const MAX_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most iterations of a `@repeat` block
+pub const MAX_REPEAT: u32 = 1000;
+
+/// Most events a block expands to, nested repeats included
+pub const MAX_BLOCK_EVENTS: usize = 10_000;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LevelMeta {
+	pub author: Option<String>,
+	/// Difficulty rating, from 1 to 5
+	pub difficulty: Option<u8>,
+	pub description: Option<String>,
+	/// File name of the music track, in `assets/audio/music`
+	pub music: Option<String>,
+	/// Background color of the play area
+	pub background: Option<[u8; 4]>,
+	/// Score to beat
+	pub par_score: Option<u64>,
+	/// The level is lost if it isn't cleared in time
+	pub time_limit: Option<Duration>,
+	/// Position in the level list when there is no campaign manifest
+	pub order: Option<i32>,
+}
+
+/// The fields set when loading the level aren't part of the RON and JSON formats
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Level {
+	#[serde(skip)]
+	pub id: u32,
+	/// File name of the level, in the levels directory
+	#[serde(skip)]
+	pub file: String,
+	#[serde(default)]
+	pub name: Rc<String>,
+	#[serde(default)]
+	pub meta: Rc<LevelMeta>,
+	/// Every condition must be met to play the level
+	#[serde(skip)]
+	pub unlock: Vec<UnlockCondition>,
+	#[serde(default)]
+	pub event_list: Vec<Event>,
+}
+
+/// Suffixes of the level files, `.hbh` or the same level written in RON or JSON
+pub const LEVEL_EXTENSIONS: [&str; 3] = [".hbh", ".hbh.ron", ".hbh.json"];
+
+/// Whether the path is a level file, wave files (`.hbw`) are only read when included by a level
+pub fn is_level_file(path: &Path) -> bool {
+	let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+	path.is_file() && LEVEL_EXTENSIONS.iter().any(|ext| file_name.ends_with(ext))
+}
+
+impl PartialEq for Level {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id
+	}
+}
+
+/// What went wrong while parsing a level file
+#[derive(Clone, Debug)]
+pub enum ParseErrorKind {
+	/// The file couldn't be read
+	Io(String),
+	/// The line ended before the expected token
+	MissingToken { expected: &'static str },
+	/// A token was found but doesn't match what was expected
+	InvalidToken { expected: &'static str, found: String },
+	/// The line has more tokens than expected
+	TrailingToken(String),
+	/// A label is given to more than one event
+	DuplicateLabel(String),
+	/// A reference uses a label that isn't given to any event
+	UnknownLabel(String),
+	/// Events inside a block can't have their own label
+	LabelInBlock(String),
+	/// An event inside a block references an event id or the block itself, every copy of the
+	/// event would reference the same event
+	RefInBlock(String),
+	/// A block is opened but the file ends before its `}`
+	UnclosedBlock,
+	/// A `}` is found outside of any block
+	UnmatchedBlockEnd,
+	/// A wave is used before being defined
+	UnknownWave(String),
+	/// Two waves have the same name
+	DuplicateWave(String),
+	/// An enemy follows a path that isn't defined before it
+	UnknownPath(String),
+	/// Two paths have the same name
+	DuplicatePath(String),
+	/// An enemy type isn't defined in the enemies directory
+	UnknownEnemy(String),
+	/// Two enemy types have the same name
+	DuplicateEnemy(String),
+	/// An enemy uses a bullet pattern that isn't defined before it
+	UnknownPattern(String),
+	/// Two bullet patterns have the same name
+	DuplicatePattern(String),
+	/// A file includes itself, directly or through other files
+	IncludeCycle(PathBuf),
+	/// A RON or JSON level doesn't match the structure of a level
+	Data(String),
+}
+
+/// Error returned by the level parser, points to the faulty token in the file
+#[derive(Clone, Debug)]
+pub struct LevelParseError {
+	pub file: PathBuf,
+	/// Line of the error, starting at 1
+	pub line: usize,
+	/// Column of the error, starting at 1
+	pub column: usize,
+	pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for LevelParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}:{}: ", self.file.display(), self.line, self.column)?;
+		match &self.kind {
+			ParseErrorKind::Io(err) => write!(f, "can't read file ({err})"),
+			ParseErrorKind::MissingToken { expected } => {
+				write!(f, "expected {expected}, found end of line")
+			},
+			ParseErrorKind::InvalidToken { expected, found } => {
+				write!(f, "expected {expected}, found '{found}'")
+			},
+			ParseErrorKind::TrailingToken(found) => write!(f, "unexpected token '{found}'"),
+			ParseErrorKind::DuplicateLabel(label) => write!(f, "label '#{label}' is already used"),
+			ParseErrorKind::UnknownLabel(label) => write!(f, "no event is labelled '#{label}'"),
+			ParseErrorKind::LabelInBlock(label) => {
+				write!(
+					f,
+					"label '#{label}' is inside a block, label the block instead"
+				)
+			},
+			ParseErrorKind::RefInBlock(found) => {
+				write!(
+					f,
+					"'{found}' can't be referenced from inside the block, use '-', a condition or the \
+					 label of an event outside of the block"
+				)
+			},
+			ParseErrorKind::UnclosedBlock => write!(f, "block is never closed by a '}}'"),
+			ParseErrorKind::UnmatchedBlockEnd => write!(f, "'}}' doesn't close any block"),
+			ParseErrorKind::UnknownWave(name) => write!(f, "wave '{name}' isn't defined"),
+			ParseErrorKind::DuplicateWave(name) => write!(f, "wave '{name}' is already defined"),
+			ParseErrorKind::UnknownPath(name) => write!(f, "path '{name}' isn't defined"),
+			ParseErrorKind::DuplicatePath(name) => write!(f, "path '{name}' is already defined"),
+			ParseErrorKind::UnknownEnemy(name) => write!(f, "enemy type '{name}' isn't defined"),
+			ParseErrorKind::DuplicateEnemy(name) => {
+				write!(f, "enemy type '{name}' is already defined")
+			},
+			ParseErrorKind::UnknownPattern(name) => write!(f, "pattern '{name}' isn't defined"),
+			ParseErrorKind::DuplicatePattern(name) => {
+				write!(f, "pattern '{name}' is already defined")
+			},
+			ParseErrorKind::IncludeCycle(path) => {
+				write!(f, "including '{}' creates a cycle", path.display())
+			},
+			ParseErrorKind::Data(err) => write!(f, "invalid level data ({err})"),
+		}
+	}
+}
+
+impl std::error::Error for LevelParseError {}
+
+/// Whitespace separated tokens of a single line, with their column
+pub struct LineTokens<'a> {
+	file: &'a Path,
+	line: usize,
+	content: &'a str,
+	offset: usize,
+	/// Column right after the end of the line, used when a token is missing
+	end: usize,
+	tokens: std::vec::IntoIter<(usize, &'a str)>,
+}
+
+impl<'a> LineTokens<'a> {
+	/// `offset` is the number of characters stripped at the beginning of the line
+	pub fn new(file: &'a Path, line: usize, content: &'a str, offset: usize) -> Self {
+		let mut tokens = vec![];
+		let mut start = None;
+		for (col, (i, c)) in content.char_indices().enumerate() {
+			match (c.is_whitespace(), start) {
+				(false, None) => start = Some((col, i)),
+				(true, Some((col_start, i_start))) => {
+					tokens.push((col_start + offset + 1, &content[i_start..i]));
+					start = None;
+				},
+				_ => {},
+			}
+		}
+		if let Some((col_start, i_start)) = start {
+			tokens.push((col_start + offset + 1, &content[i_start..]));
+		}
+		let end = content.chars().count() + offset + 1;
+		LineTokens { file, line, content, offset, end, tokens: tokens.into_iter() }
+	}
+
+	pub fn error(&self, column: usize, kind: ParseErrorKind) -> LevelParseError {
+		LevelParseError { file: self.file.to_path_buf(), line: self.line, column, kind }
+	}
+
+	pub fn next(&mut self, expected: &'static str) -> Result<(usize, &'a str), LevelParseError> {
+		self
+			.tokens
+			.next()
+			.ok_or_else(|| self.error(self.end, ParseErrorKind::MissingToken { expected }))
+	}
+
+	/// Reads the rest of the line as a single value, spaces included
+	pub fn rest(&mut self, expected: &'static str) -> Result<(usize, String), LevelParseError> {
+		let (col, _) = self.next(expected)?;
+		self.tokens.by_ref().for_each(drop);
+		Ok((
+			col,
+			self.content.chars().skip(col - self.offset - 1).collect(),
+		))
+	}
+
+	fn peek(&self) -> Option<&(usize, &'a str)> {
+		self.tokens.as_slice().first()
+	}
+
+	pub fn parse<T: FromStr>(&mut self, expected: &'static str) -> Result<T, LevelParseError> {
+		let (col, token) = self.next(expected)?;
+		token.parse().map_err(|_| {
+			self.error(
+				col,
+				ParseErrorKind::InvalidToken { expected, found: token.into() },
+			)
+		})
+	}
+
+	/// Parses a number checked by `valid`, `f32` also parses "inf" and "NaN"
+	fn parse_number(
+		&mut self,
+		expected: &'static str,
+		valid: impl Fn(f32) -> bool,
+	) -> Result<f32, LevelParseError> {
+		let (col, token) = self.next(expected)?;
+		match token.parse::<f32>() {
+			Ok(value) if valid(value) => Ok(value),
+			_ => Err(self.error(
+				col,
+				ParseErrorKind::InvalidToken { expected, found: token.into() },
+			)),
+		}
+	}
+
+	/// Parses a finite number
+	pub fn parse_finite(&mut self, expected: &'static str) -> Result<f32, LevelParseError> {
+		self.parse_number(expected, f32::is_finite)
+	}
+
+	/// Parses a finite number greater than 0
+	pub fn parse_positive(&mut self, expected: &'static str) -> Result<f32, LevelParseError> {
+		self.parse_number(expected, |value| value.is_finite() && value > 0.)
+	}
+
+	/// Reads the optional `#label` at the end of an event line
+	fn label(&mut self) -> Result<Option<(usize, &'a str)>, LevelParseError> {
+		match self.tokens.as_slice().first() {
+			Some(&(col, token)) if token.starts_with('#') => {
+				self.tokens.next();
+				let label = &token[1..];
+				// The generated form of block labels is accepted, it is used when writing levels
+				if !is_label_ref(label) {
+					return Err(self.error(
+						col,
+						ParseErrorKind::InvalidToken {
+							expected: "label ('#' followed by letters, digits, '-' or '_')",
+							found: token.into(),
+						},
+					));
+				}
+				Ok(Some((col, label)))
+			},
+			_ => Ok(None),
+		}
+	}
+
+	/// Ensures nothing is left on the line
+	pub fn finish(mut self) -> Result<(), LevelParseError> {
+		match self.tokens.next() {
+			Some((col, token)) => Err(self.error(col, ParseErrorKind::TrailingToken(token.into()))),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Labels start with a letter or '_' so they can't be mistaken for an event index
+pub fn is_label(label: &str) -> bool {
+	label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+		&& label
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Labels generated for the events of a block are suffixed by the index of the event
+fn is_label_ref(label: &str) -> bool {
+	match label.split_once('.') {
+		Some((base, index)) => {
+			is_label(base) && !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+		},
+		None => is_label(label),
+	}
+}
+
+/// Whether the event labelled `label` is part of the wave `wave`, which is either the label of an
+/// event or of a block
+pub fn is_in_wave(label: &str, wave: &str) -> bool {
+	label
+		.strip_prefix(wave)
+		.is_some_and(|suffix| suffix.is_empty() || suffix.starts_with('.'))
+}
+
+/// Reference column of an event before labels are resolved
+#[derive(Clone, Debug)]
+enum RefToken {
+	Level,
+	Id(u32),
+	/// Label with the position of the token, to report unknown labels
+	Label {
+		label: String,
+		line: usize,
+		column: usize,
+	},
+	/// Wave label of a `cleared:` trigger, with the position of the token
+	Cleared {
+		wave: String,
+		line: usize,
+		column: usize,
+	},
+	/// Trigger that doesn't reference events
+	Condition(Trigger),
+}
+
+/// Event read from the file, before its reference is resolved
+#[derive(Clone, Debug)]
+struct RawEvent {
+	variant: EventType,
+	t: Duration,
+	ref_evt: RefToken,
+	label: Option<String>,
+}
+
+impl RawEvent {
+	fn shift(&mut self, offset: Vector2<f32>) {
+		match &mut self.variant {
+			EventType::_SpawnEnemy(pos, ..) => *pos += offset,
+			EventType::_SpawnBoss(pos, _) => *pos += offset,
+		}
+	}
+}
+
+/// Line of a level file, without its indentation
+struct SourceLine<'a> {
+	number: usize,
+	/// Column of the first character of `content`
+	column: usize,
+	content: &'a str,
+}
+
+/// Where an event line is found, some events are only allowed in some places
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scope {
+	/// Top level of a level file
+	Level,
+	/// Inside a `{ ... }` block
+	Block,
+	/// Top level of an included file, which can only define waves and paths
+	WaveFile,
+}
+
+/// State shared by the parsers of a level file and of the files it includes
+#[derive(Default)]
+struct ParseState {
+	/// Labels already given to an event, to detect duplicates while parsing
+	declared: HashSet<String>,
+	/// Events of the wave templates, by name
+	waves: HashMap<String, Vec<RawEvent>>,
+	/// Enemy paths, by name
+	paths: HashMap<String, Rc<EnemyPath>>,
+	/// Files being parsed, from the level file to the current include, to detect cycles
+	include_stack: Vec<PathBuf>,
+	/// Files already included, a file included twice is only read once
+	included: HashSet<PathBuf>,
+}
+
+struct LevelParser<'a> {
+	file: &'a Path,
+	lines: std::iter::Enumerate<std::str::Lines<'a>>,
+	state: ParseState,
+	/// Enemy types that can be spawned
+	enemy_types: &'a EnemyTypes,
+}
+
+impl<'a> LevelParser<'a> {
+	fn new(
+		file: &'a Path,
+		content: &'a str,
+		state: ParseState,
+		enemy_types: &'a EnemyTypes,
+	) -> Self {
+		LevelParser { file, lines: content.lines().enumerate(), state, enemy_types }
+	}
+
+	/// Returns the next line without its indentation
+	fn next_line(&mut self) -> Option<SourceLine<'a>> {
+		self.lines.next().map(|(i, line)| {
+			let content = line.trim_start();
+			let column = line.chars().count() - content.chars().count() + 1;
+			SourceLine { number: i + 1, column, content: content.trim_end() }
+		})
+	}
+
+	fn error(&self, line: usize, column: usize, kind: ParseErrorKind) -> LevelParseError {
+		LevelParseError { file: self.file.to_path_buf(), line, column, kind }
+	}
+
+	/// Reads the optional label of an event, labels can only be declared outside of blocks
+	fn declare_label(
+		&mut self,
+		tokens: &mut LineTokens,
+		scope: Scope,
+	) -> Result<Option<String>, LevelParseError> {
+		let Some((col, label)) = tokens.label()? else {
+			return Ok(None);
+		};
+		if scope != Scope::Level {
+			return Err(tokens.error(col, ParseErrorKind::LabelInBlock(label.into())));
+		}
+		// `base.N` labels would clash with the events of a block labelled `base`
+		let clash = match label.split_once('.') {
+			Some((base, _)) => self.state.declared.contains(base),
+			None => self
+				.state
+				.declared
+				.iter()
+				.any(|declared| is_in_wave(declared, label)),
+		};
+		if clash || !self.state.declared.insert(label.into()) {
+			return Err(tokens.error(col, ParseErrorKind::DuplicateLabel(label.into())));
+		}
+		Ok(Some(label.into()))
+	}
+
+	/// Parses the lines of the file, `level` is `None` for included files
+	fn parse_file(
+		&mut self,
+		mut level: Option<&mut Level>,
+	) -> Result<Vec<RawEvent>, LevelParseError> {
+		let scope = match level {
+			Some(_) => Scope::Level,
+			None => Scope::WaveFile,
+		};
+		let mut events = vec![];
+		while let Some(line) = self.next_line() {
+			if line.content.starts_with('$') {
+				self.parse_metadata(level.as_deref_mut(), &line)?;
+			} else if line.content.starts_with('@') {
+				events.extend(self.parse_event(&line, scope)?);
+			} else if line.content == "}" {
+				return Err(self.error(line.number, line.column, ParseErrorKind::UnmatchedBlockEnd));
+			}
+		}
+		Ok(events)
+	}
+
+	fn parse_metadata(
+		&mut self,
+		level: Option<&mut Level>,
+		line: &SourceLine,
+	) -> Result<(), LevelParseError> {
+		let data = &line.content[1..];
+		let mut tokens = LineTokens::new(self.file, line.number, data, line.column);
+		let (col, key) = tokens.next("metadata key")?;
+		let Some(level) = level else {
+			if key != "include" {
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken {
+						expected: "metadata key (include)",
+						found: key.into(),
+					},
+				));
+			}
+			let (col, path) = tokens.rest("path of the included file")?;
+			return self.include(line.number, col, &path);
+		};
+		let meta = Rc::make_mut(&mut level.meta);
+		match key {
+			"include" => {
+				let (col, path) = tokens.rest("path of the included file")?;
+				self.include(line.number, col, &path)?;
+			},
+			"title" => {
+				let (_, title) = tokens.rest("level title")?;
+				level.name = Rc::new(title);
+			},
+			"author" => meta.author = Some(tokens.rest("author name")?.1),
+			"description" => {
+				let (_, description) = tokens.rest("level description")?;
+				// Long descriptions can be split on several lines
+				meta.description = Some(match meta.description.take() {
+					Some(previous) => format!("{previous} {description}"),
+					None => description,
+				});
+			},
+			"difficulty" => {
+				const EXPECTED: &str = "difficulty (1 to 5)";
+				let (col, token) = tokens.next(EXPECTED)?;
+				match token.parse::<u8>() {
+					Ok(difficulty @ 1..=5) => meta.difficulty = Some(difficulty),
+					_ => {
+						return Err(tokens.error(
+							col,
+							ParseErrorKind::InvalidToken { expected: EXPECTED, found: token.into() },
+						))
+					},
+				}
+			},
+			"music" => meta.music = Some(tokens.rest("music file name")?.1),
+			"background" => {
+				const EXPECTED: &str = "background color (#rrggbb or palette name)";
+				let (col, token) = tokens.next(EXPECTED)?;
+				meta.background = Some(parse_color(token).ok_or_else(|| {
+					tokens.error(
+						col,
+						ParseErrorKind::InvalidToken { expected: EXPECTED, found: token.into() },
+					)
+				})?);
+			},
+			"par-score" => meta.par_score = Some(tokens.parse("par score")?),
+			"time-limit" => {
+				let time_limit = parse_time(&mut tokens)?;
+				meta.time_limit = (!time_limit.is_zero()).then_some(time_limit);
+			},
+			"order" => meta.order = Some(tokens.parse("level order")?),
+			key => {
+				const EXPECTED: &str = "metadata key (title, author, description, difficulty, music, \
+					background, par-score, time-limit, order, include)";
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken { expected: EXPECTED, found: key.into() },
+				));
+			},
+		}
+		tokens.finish()
+	}
+
+	/// Reads the wave definitions of another file, the path is relative to the current file
+	fn include(&mut self, line: usize, column: usize, path: &str) -> Result<(), LevelParseError> {
+		let path = self.file.parent().unwrap_or(Path::new("")).join(path);
+		let io_error = |err: std::io::Error| ParseErrorKind::Io(format!("{}: {err}", path.display()));
+		let canonical = match fs::canonicalize(&path) {
+			Ok(canonical) => canonical,
+			Err(err) => return Err(self.error(line, column, io_error(err))),
+		};
+		if self.state.include_stack.contains(&canonical) {
+			return Err(self.error(line, column, ParseErrorKind::IncludeCycle(path)));
+		}
+		if !self.state.included.insert(canonical.clone()) {
+			return Ok(());
+		}
+		let content = match fs::read_to_string(&path) {
+			Ok(content) => content,
+			Err(err) => return Err(self.error(line, column, io_error(err))),
+		};
+
+		let state = std::mem::take(&mut self.state);
+		let mut parser = LevelParser::new(&path, &content, state, self.enemy_types);
+		parser.state.include_stack.push(canonical);
+		parser.parse_file(None)?;
+		parser.state.include_stack.pop();
+		self.state = parser.state;
+		Ok(())
+	}
+
+	/// Parses an event line, which may open a block spanning the following lines
+	fn parse_event(
+		&mut self,
+		line: &SourceLine,
+		scope: Scope,
+	) -> Result<Vec<RawEvent>, LevelParseError> {
+		let mut tokens = LineTokens::new(self.file, line.number, &line.content[1..], line.column);
+		let (col, evt_type) = tokens.next("event type")?;
+		let expected = match scope {
+			Scope::Level => "event type (spawn-enemy, spawn-boss, repeat, wave, use-wave, path)",
+			Scope::Block => "event type (spawn-enemy, spawn-boss, repeat, use-wave)",
+			Scope::WaveFile => "wave or path definition (wave, path)",
+		};
+		let allowed = match scope {
+			Scope::Level => true,
+			Scope::Block => !matches!(evt_type, "wave" | "path"),
+			Scope::WaveFile => matches!(evt_type, "wave" | "path"),
+		};
+		if !allowed {
+			return Err(tokens.error(
+				col,
+				ParseErrorKind::InvalidToken { expected, found: evt_type.into() },
+			));
+		}
+		match evt_type {
+			"spawn-enemy" => {
+				let (col, variant) = tokens.next("enemy type")?;
+				// The type can be followed by the path of the enemy, `basic:<path>`
+				let (variant, path) = match variant.split_once(':') {
+					Some((variant, name)) => match self.state.paths.get(name) {
+						Some(path) => (variant, Some(path.clone())),
+						None => {
+							let col = col + variant.chars().count() + 1;
+							return Err(tokens.error(col, ParseErrorKind::UnknownPath(name.into())));
+						},
+					},
+					None => (variant, None),
+				};
+				if self.enemy_types.get(variant).is_none() {
+					return Err(tokens.error(col, ParseErrorKind::UnknownEnemy(variant.into())));
+				}
+				let t = parse_time(&mut tokens)?;
+				let x = tokens.parse_finite("x coordinate")?;
+				let y = tokens.parse_finite("y coordinate")?;
+				let ref_evt = parse_ref(&mut tokens, scope)?;
+				let label = self.declare_label(&mut tokens, scope)?;
+				tokens.finish()?;
+				let variant = EventType::_SpawnEnemy((x, y).into(), variant.into(), path);
+				Ok(vec![RawEvent { variant, t, ref_evt, label }])
+			},
+			"spawn-boss" => {
+				let (_, name) = tokens.next("boss name")?;
+				let t = parse_time(&mut tokens)?;
+				let x = tokens.parse_finite("x coordinate")?;
+				let y = tokens.parse_finite("y coordinate")?;
+				let ref_evt = parse_ref(&mut tokens, scope)?;
+				let label = self.declare_label(&mut tokens, scope)?;
+				let (phases, hitbox) = self.parse_phases(&mut tokens)?;
+				tokens.finish()?;
+				let def = BossDef { name: name.into(), hitbox, phases };
+				let variant = EventType::_SpawnBoss((x, y).into(), def);
+				Ok(vec![RawEvent { variant, t, ref_evt, label }])
+			},
+			"repeat" => {
+				const COUNT_EXPECTED: &str = "repeat count (1 to 1000)";
+				let (count_col, count_token) = tokens.next(COUNT_EXPECTED)?;
+				let count = match count_token.parse::<u32>() {
+					Ok(count) if (1..=MAX_REPEAT).contains(&count) => count,
+					_ => {
+						return Err(tokens.error(
+							count_col,
+							ParseErrorKind::InvalidToken {
+								expected: COUNT_EXPECTED,
+								found: count_token.into(),
+							},
+						))
+					},
+				};
+				let interval = parse_time(&mut tokens)?;
+				// Optional position offsets applied at each iteration
+				let mut offset: Vector2<f32> = Vector2::zero();
+				while let Some(&(col, token)) = tokens.peek() {
+					let (axis, value) = match token.split_once("+=").or(token.split_once("-=")) {
+						Some((axis @ ("x" | "y"), value)) => (axis, value),
+						_ => break,
+					};
+					tokens.next("position offset")?;
+					let value = match value.parse::<f32>() {
+						Ok(value) if value.is_finite() => value,
+						_ => {
+							return Err(tokens.error(
+								col,
+								ParseErrorKind::InvalidToken {
+									expected: "position offset (x+=<dx> or y+=<dy>)",
+									found: token.into(),
+								},
+							))
+						},
+					};
+					let value = if token.contains("-=") { -value } else { value };
+					match axis {
+						"x" => offset.x = value,
+						_ => offset.y = value,
+					}
+				}
+				let label_col = tokens.peek().map(|&(col, _)| col);
+				let label = self.declare_label(&mut tokens, scope)?;
+				let body = self.parse_block(&mut tokens)?;
+				self.check_block_refs(&body, label.as_deref(), line.number, label_col)?;
+				if body.len() * count as usize > MAX_BLOCK_EVENTS {
+					return Err(self.error(
+						line.number,
+						count_col,
+						ParseErrorKind::InvalidToken {
+							expected: "repeat count expanding to at most 10000 events",
+							found: count_token.into(),
+						},
+					));
+				}
+
+				let mut expanded = vec![];
+				for i in 0..count {
+					for evt in body.iter() {
+						let mut evt = evt.clone();
+						evt.t += interval * i;
+						evt.shift(offset * i as f32);
+						expanded.push(evt);
+					}
+				}
+				label_block(&mut expanded, label);
+				Ok(expanded)
+			},
+			"wave" => {
+				const NAME_EXPECTED: &str = "wave name (letters, digits, '-' or '_')";
+				let (col, name) = tokens.next(NAME_EXPECTED)?;
+				if !is_label(name) {
+					return Err(tokens.error(
+						col,
+						ParseErrorKind::InvalidToken { expected: NAME_EXPECTED, found: name.into() },
+					));
+				}
+				if self.state.waves.contains_key(name) {
+					return Err(tokens.error(col, ParseErrorKind::DuplicateWave(name.into())));
+				}
+				let body = self.parse_block(&mut tokens)?;
+				self.state.waves.insert(name.into(), body);
+				Ok(vec![])
+			},
+			"path" => {
+				const NAME_EXPECTED: &str = "path name (letters, digits, '-' or '_')";
+				let (col, name) = tokens.next(NAME_EXPECTED)?;
+				if !is_label(name) {
+					return Err(tokens.error(
+						col,
+						ParseErrorKind::InvalidToken { expected: NAME_EXPECTED, found: name.into() },
+					));
+				}
+				if self.state.paths.contains_key(name) {
+					return Err(tokens.error(col, ParseErrorKind::DuplicatePath(name.into())));
+				}
+				let segments = self.parse_segments(&mut tokens)?;
+				let path = EnemyPath { name: name.into(), segments };
+				self.state.paths.insert(name.into(), Rc::new(path));
+				Ok(vec![])
+			},
+			"use-wave" => {
+				let (col, name) = tokens.next("wave name")?;
+				let t = parse_time(&mut tokens)?;
+				let x = tokens.parse_finite("x coordinate")?;
+				let y = tokens.parse_finite("y coordinate")?;
+				let ref_evt = parse_ref(&mut tokens, scope)?;
+				let label_col = tokens.peek().map(|&(col, _)| col);
+				let label = self.declare_label(&mut tokens, scope)?;
+				tokens.finish()?;
+				let Some(body) = self.state.waves.get(name) else {
+					return Err(self.error(line.number, col, ParseErrorKind::UnknownWave(name.into())));
+				};
+				self.check_block_refs(body, label.as_deref(), line.number, label_col)?;
+
+				// Events of the wave relative to the beginning of the level become relative to
+				// the reference of the wave
+				let mut expanded = body.clone();
+				for evt in expanded.iter_mut() {
+					if matches!(evt.ref_evt, RefToken::Level) {
+						evt.ref_evt = ref_evt.clone();
+						evt.t += t;
+					}
+					evt.shift((x, y).into());
+				}
+				label_block(&mut expanded, label);
+				Ok(expanded)
+			},
+			evt => Err(tokens.error(
+				col,
+				ParseErrorKind::InvalidToken { expected, found: evt.into() },
+			)),
+		}
+	}
+
+	/// Events of a block can't reference the events of the block, every copy would reference the
+	/// first copy, the error is on the label of the block at `line` and `column`
+	fn check_block_refs(
+		&self,
+		events: &[RawEvent],
+		label: Option<&str>,
+		line: usize,
+		column: Option<usize>,
+	) -> Result<(), LevelParseError> {
+		let Some(label) = label else {
+			return Ok(());
+		};
+		for evt in events {
+			if let RefToken::Label { label: target, .. } | RefToken::Cleared { wave: target, .. } =
+				&evt.ref_evt
+			{
+				if is_in_wave(target, label) {
+					let kind = ParseErrorKind::RefInBlock(target.clone());
+					return Err(self.error(line, column.unwrap_or_default(), kind));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Parses the `@phase` lines and the optional `$hitbox` of a boss until the closing `}`
+	fn parse_phases(
+		&mut self,
+		tokens: &mut LineTokens,
+	) -> Result<(Vec<BossPhase>, Option<Shape>), LevelParseError> {
+		let (open_col, open) = tokens.next("'{' opening the phases of the boss")?;
+		if open != "{" {
+			return Err(tokens.error(
+				open_col,
+				ParseErrorKind::InvalidToken {
+					expected: "'{' opening the phases of the boss",
+					found: open.into(),
+				},
+			));
+		}
+		let open_line = tokens.line;
+		let mut phases = vec![];
+		let mut hitbox = None;
+		while let Some(line) = self.next_line() {
+			if line.content == "}" {
+				if phases.is_empty() {
+					return Err(self.error(
+						line.number,
+						line.column,
+						ParseErrorKind::InvalidToken {
+							expected: "boss phase (@phase)",
+							found: "}".into(),
+						},
+					));
+				}
+				return Ok((phases, hitbox));
+			}
+			// Other lines are comments, like in the rest of the file
+			if !line.content.starts_with(['@', '$']) {
+				continue;
+			}
+			let mut tokens = LineTokens::new(self.file, line.number, &line.content[1..], line.column);
+			let (_, key) = tokens.next("boss phase (@phase)")?;
+			if line.content.starts_with('$') && key == "hitbox" {
+				hitbox = Some(parse_shape(&mut tokens)?);
+				tokens.finish()?;
+				continue;
+			}
+			if !line.content.starts_with('@') || key != "phase" {
+				return Err(self.error(
+					line.number,
+					line.column,
+					ParseErrorKind::InvalidToken {
+						expected: "boss phase (@phase), hitbox ($hitbox) or '}' closing the phases",
+						found: line.content.into(),
+					},
+				));
+			}
+			let hp = tokens.parse_positive("phase HP (positive number)")?;
+			let timeout = match tokens.peek() {
+				Some((_, "-")) => {
+					tokens.next("phase timeout")?;
+					None
+				},
+				_ => Some(parse_time(&mut tokens)?).filter(|timeout| !timeout.is_zero()),
+			};
+			let (movement_col, movement) = tokens.next("boss movement")?;
+			let movement = match movement {
+				"hover" => BossMovement::Hover,
+				"sweep" => BossMovement::Sweep,
+				"chase" => BossMovement::Chase,
+				other => {
+					return Err(tokens.error(
+						movement_col,
+						ParseErrorKind::InvalidToken {
+							expected: "boss movement (hover, sweep, chase)",
+							found: other.into(),
+						},
+					))
+				},
+			};
+			let (pattern_col, pattern) = tokens.next("bullet pattern")?;
+			let pattern = parse_pattern(pattern).ok_or_else(|| {
+				tokens.error(
+					pattern_col,
+					ParseErrorKind::InvalidToken {
+						expected: "bullet pattern (down, aimed, spread, ring)",
+						found: pattern.into(),
+					},
+				)
+			})?;
+			let fire_interval = parse_time(&mut tokens)?;
+			tokens.finish()?;
+			phases.push(BossPhase { hp, timeout, movement, pattern, fire_interval });
+		}
+		Err(self.error(open_line, open_col, ParseErrorKind::UnclosedBlock))
+	}
+
+	/// Parses the segments of a path until the closing `}`
+	fn parse_segments(
+		&mut self,
+		tokens: &mut LineTokens,
+	) -> Result<Vec<PathSegment>, LevelParseError> {
+		let (open_col, open) = tokens.next("'{' opening the segments of the path")?;
+		if open != "{" {
+			return Err(tokens.error(
+				open_col,
+				ParseErrorKind::InvalidToken {
+					expected: "'{' opening the segments of the path",
+					found: open.into(),
+				},
+			));
+		}
+		let open_line = tokens.line;
+		let mut segments = vec![];
+		while let Some(line) = self.next_line() {
+			if line.content == "}" {
+				if segments.is_empty() {
+					return Err(self.error(
+						line.number,
+						line.column,
+						ParseErrorKind::InvalidToken {
+							expected: "path segment (line, bezier, spline, hold)",
+							found: "}".into(),
+						},
+					));
+				}
+				return Ok(segments);
+			}
+			if !line.content.starts_with(['@', '$']) {
+				continue;
+			}
+			let mut tokens = LineTokens::new(self.file, line.number, &line.content[1..], line.column);
+			let (col, key) = tokens.next("path segment (line, bezier, spline, hold)")?;
+			let segment = match key {
+				"hold" if line.content.starts_with('@') => PathSegment::Hold(parse_time(&mut tokens)?),
+				"line" | "bezier" | "spline" if line.content.starts_with('@') => {
+					let (points, speed) = parse_points(&mut tokens, key == "line")?;
+					match key {
+						"line" => PathSegment::Line { to: points[0], speed },
+						"bezier" => PathSegment::Bezier { points, speed },
+						_ => PathSegment::Spline { points, speed },
+					}
+				},
+				_ => {
+					return Err(tokens.error(
+						col,
+						ParseErrorKind::InvalidToken {
+							expected: "path segment (@line, @bezier, @spline, @hold) or '}' closing \
+								the path",
+							found: line.content.into(),
+						},
+					))
+				},
+			};
+			tokens.finish()?;
+			segments.push(segment);
+		}
+		Err(self.error(open_line, open_col, ParseErrorKind::UnclosedBlock))
+	}
+
+	/// Parses the lines of a block until its closing `}`, `tokens` must end with the opening `{`
+	fn parse_block(&mut self, tokens: &mut LineTokens) -> Result<Vec<RawEvent>, LevelParseError> {
+		let (open_col, open) = tokens.next("'{' opening the block")?;
+		if open != "{" {
+			return Err(tokens.error(
+				open_col,
+				ParseErrorKind::InvalidToken { expected: "'{' opening the block", found: open.into() },
+			));
+		}
+		let open_line = tokens.line;
+		let mut events = vec![];
+		while let Some(line) = self.next_line() {
+			if line.content == "}" {
+				return Ok(events);
+			} else if line.content.starts_with('@') {
+				events.extend(self.parse_event(&line, Scope::Block)?);
+			} else if line.content.starts_with('$') {
+				return Err(self.error(
+					line.number,
+					line.column,
+					ParseErrorKind::InvalidToken {
+						expected: "event or '}' closing the block",
+						found: line.content.into(),
+					},
+				));
+			}
+		}
+		Err(self.error(open_line, open_col, ParseErrorKind::UnclosedBlock))
+	}
+}
+
+/// Parses the `<x> <y>` points of a path segment and the speed ending the line, `single` only
+/// allows one point
+fn parse_points(
+	tokens: &mut LineTokens,
+	single: bool,
+) -> Result<(Vec<Point2<f32>>, f32), LevelParseError> {
+	const EXPECTED: &str = "coordinate or speed (number)";
+	let mut numbers = vec![];
+	while let Some(&(col, token)) = tokens.peek() {
+		if single && numbers.len() == 3 {
+			break;
+		}
+		tokens.next(EXPECTED)?;
+		match token.parse::<f32>() {
+			Ok(number) if number.is_finite() => numbers.push((col, number)),
+			_ => {
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken { expected: EXPECTED, found: token.into() },
+				))
+			},
+		}
+	}
+	if numbers.len() < 3 || numbers.len() % 2 == 0 {
+		let expected = match numbers.len() {
+			0 => "x coordinate",
+			1 => "y coordinate",
+			_ => "speed in pixels per second",
+		};
+		return Err(tokens.error(tokens.end, ParseErrorKind::MissingToken { expected }));
+	}
+	const SPEED_EXPECTED: &str = "speed in pixels per second (positive number)";
+	let ((speed_col, speed), coords) = (numbers[numbers.len() - 1], &numbers[..numbers.len() - 1]);
+	if speed <= 0. {
+		return Err(tokens.error(
+			speed_col,
+			ParseErrorKind::InvalidToken { expected: SPEED_EXPECTED, found: speed.to_string() },
+		));
+	}
+	let points = coords
+		.chunks(2)
+		.map(|xy| Point2::new(xy[0].1, xy[1].1))
+		.collect();
+	Ok((points, speed))
+}
+
+/// Names the events created by a labelled block `<label>.<n>`
+fn label_block(events: &mut [RawEvent], label: Option<String>) {
+	if let Some(label) = label {
+		for (i, evt) in events.iter_mut().enumerate() {
+			evt.label = Some(format!("{label}.{i}"));
+		}
+	}
+}
+
+/// Parses the reference column: `-` for the beginning of the level, an event id, a label or a
+/// condition (`cleared:<label>`, `score:<n>`, `hit`, `enemies-below:<n>`)
+fn parse_ref(tokens: &mut LineTokens, scope: Scope) -> Result<RefToken, LevelParseError> {
+	const EXPECTED: &str = "reference event ('-', event id, label, cleared:<label>, score:<n>, hit \
+		or enemies-below:<n>)";
+	let (col, ref_evt) = tokens.next(EXPECTED)?;
+	let invalid = || {
+		tokens.error(
+			col,
+			ParseErrorKind::InvalidToken { expected: EXPECTED, found: ref_evt.into() },
+		)
+	};
+	if let Some((condition, value)) = ref_evt.split_once(':') {
+		return match condition {
+			"cleared" if is_label_ref(value) => {
+				Ok(RefToken::Cleared { wave: value.into(), line: tokens.line, column: col })
+			},
+			"score" => value
+				.parse()
+				.map(|score| RefToken::Condition(Trigger::Score(score))),
+			// Less than no enemy is never true
+			"enemies-below" => match value.parse() {
+				Ok(0) => return Err(invalid()),
+				n => n.map(|n| RefToken::Condition(Trigger::EnemiesBelow(n))),
+			},
+			_ => return Err(invalid()),
+		}
+		.map_err(|_| invalid());
+	}
+	let label = ref_evt.trim_start_matches('#');
+	match ref_evt.parse::<u32>() {
+		_ if ref_evt == "-" => Ok(RefToken::Level),
+		_ if ref_evt == "hit" => Ok(RefToken::Condition(Trigger::PlayerHit)),
+		// Ids inside a block would be the same for every copy of the block
+		Ok(_) if scope == Scope::Block => {
+			Err(tokens.error(col, ParseErrorKind::RefInBlock(ref_evt.into())))
+		},
+		Ok(ref_id) => Ok(RefToken::Id(ref_id)),
+		Err(_) if is_label_ref(label) => {
+			Ok(RefToken::Label { label: label.into(), line: tokens.line, column: col })
+		},
+		Err(_) => Err(invalid()),
+	}
+}
+
+impl Level {
+	/// Reads a level file, its format is chosen from its extension
+	pub fn load(
+		level_file: &Path,
+		id: u32,
+		enemy_types: &EnemyTypes,
+	) -> Result<Level, LevelParseError> {
+		let file_name = file_name(level_file);
+		if file_name.ends_with(".hbh.ron") || file_name.ends_with(".hbh.json") {
+			Level::data_parser(level_file, id, enemy_types)
+		} else {
+			Level::level_parser(level_file, id, enemy_types)
+		}
+	}
+
+	/// Reads a level written in RON or JSON, the result is the same as for the equivalent `.hbh`
+	pub fn data_parser(
+		level_file: &Path,
+		id: u32,
+		enemy_types: &EnemyTypes,
+	) -> Result<Level, LevelParseError> {
+		let raw_data = fs::read_to_string(level_file).map_err(|err| LevelParseError {
+			file: level_file.to_path_buf(),
+			line: 0,
+			column: 0,
+			kind: ParseErrorKind::Io(err.to_string()),
+		})?;
+		Level::parse_data(level_file, &raw_data, id, enemy_types)
+	}
+
+	/// Parses the content of a RON file, or JSON if `level_file` ends with `.json`
+	pub fn parse_data(
+		level_file: &Path,
+		raw_data: &str,
+		id: u32,
+		enemy_types: &EnemyTypes,
+	) -> Result<Level, LevelParseError> {
+		let error = |line: usize, column: usize, kind: ParseErrorKind| LevelParseError {
+			file: level_file.to_path_buf(),
+			line,
+			column,
+			kind,
+		};
+		let mut level: Level = if level_file.extension().is_some_and(|ext| ext == "json") {
+			serde_json::from_str(raw_data).map_err(|err| {
+				error(
+					err.line(),
+					err.column(),
+					ParseErrorKind::Data(err.to_string()),
+				)
+			})?
+		} else {
+			ron::from_str(raw_data).map_err(|err| {
+				let start = err.span.start;
+				error(
+					start.line,
+					start.col,
+					ParseErrorKind::Data(err.code.to_string()),
+				)
+			})?
+		};
+
+		// Same checks and normalization as the `.hbh` parser
+		if let Some(difficulty) = level.meta.difficulty.filter(|d| !(1..=5).contains(d)) {
+			let found = difficulty.to_string();
+			let kind = ParseErrorKind::InvalidToken { expected: "difficulty (1 to 5)", found };
+			return Err(error(0, 0, kind));
+		}
+		let mut paths: HashMap<&str, &EnemyPath> = HashMap::new();
+		for evt in &level.event_list {
+			if let EventType::_SpawnEnemy(_, name, _) = &evt.variant {
+				if enemy_types.get(name).is_none() {
+					return Err(error(0, 0, ParseErrorKind::UnknownEnemy(name.clone())));
+				}
+			}
+			if let EventType::_SpawnEnemy(_, _, Some(path)) = &evt.variant {
+				if paths
+					.insert(&path.name, path)
+					.is_some_and(|other| other != path.as_ref())
+				{
+					return Err(error(
+						0,
+						0,
+						ParseErrorKind::DuplicatePath(path.name.clone()),
+					));
+				}
+				let invalid = |expected, found: String| {
+					Err(error(
+						0,
+						0,
+						ParseErrorKind::InvalidToken { expected, found },
+					))
+				};
+				if path.segments.is_empty() {
+					return invalid("path segment", format!("no segment in path {}", path.name));
+				}
+				for segment in &path.segments {
+					if segment
+						.speed()
+						.is_some_and(|speed| !speed.is_finite() || speed <= 0.)
+					{
+						let speed = segment.speed().unwrap_or_default();
+						return invalid(
+							"speed in pixels per second (positive number)",
+							speed.to_string(),
+						);
+					}
+					if segment.points().is_empty() && segment.speed().is_some() {
+						return invalid(
+							"point",
+							format!("no point in a segment of path {}", path.name),
+						);
+					}
+				}
+			}
+			let EventType::_SpawnBoss(_, def) = &evt.variant else {
+				continue;
+			};
+			if def.phases.is_empty() {
+				let kind = ParseErrorKind::InvalidToken {
+					expected: "boss phase",
+					found: format!("no phase for boss {}", def.name),
+				};
+				return Err(error(0, 0, kind));
+			}
+			if let Some(phase) = def
+				.phases
+				.iter()
+				.find(|phase| !phase.hp.is_finite() || phase.hp <= 0.)
+			{
+				let kind = ParseErrorKind::InvalidToken {
+					expected: "phase HP (positive number)",
+					found: phase.hp.to_string(),
+				};
+				return Err(error(0, 0, kind));
+			}
+			if let Some(hitbox) = def.hitbox.filter(|hitbox| !hitbox.is_valid()) {
+				let kind = ParseErrorKind::InvalidToken {
+					expected: "boss hitbox (sizes and radius greater than 0)",
+					found: format_shape(&hitbox),
+				};
+				return Err(error(0, 0, kind));
+			}
+		}
+		// Events without trigger wait for the beginning of the level, like `-` in `.hbh` files
+		for evt in level.event_list.iter_mut() {
+			let (trigger, _) = evt
+				.trigger
+				.get_or_insert((Trigger::Event(None), Duration::ZERO));
+			if *trigger == Trigger::EnemiesBelow(0) {
+				let kind = ParseErrorKind::InvalidToken {
+					expected: "enemy count (positive integer)",
+					found: "EnemiesBelow(0)".into(),
+				};
+				return Err(error(0, 0, kind));
+			}
+		}
+		let meta = Rc::make_mut(&mut level.meta);
+		meta.time_limit = meta.time_limit.filter(|time_limit| !time_limit.is_zero());
+		level.id = id;
+		level.file = file_name(level_file);
+		for (id, evt) in level.event_list.iter_mut().enumerate() {
+			evt.id = id as u32;
+		}
+		Ok(level)
+	}
+
+	pub fn level_parser(
+		level_file: &Path,
+		id: u32,
+		enemy_types: &EnemyTypes,
+	) -> Result<Level, LevelParseError> {
+		let level_raw_data = fs::read_to_string(level_file).map_err(|err| LevelParseError {
+			file: level_file.to_path_buf(),
+			line: 0,
+			column: 0,
+			kind: ParseErrorKind::Io(err.to_string()),
+		})?;
+		Level::parse_hbh(level_file, &level_raw_data, id, enemy_types)
+	}
+
+	/// Parses the content of a `.hbh` file, includes are relative to `level_file`
+	pub fn parse_hbh(
+		level_file: &Path,
+		level_raw_data: &str,
+		id: u32,
+		enemy_types: &EnemyTypes,
+	) -> Result<Level, LevelParseError> {
+		let mut level = Level {
+			id,
+			file: file_name(level_file),
+			event_list: vec![],
+			name: Rc::new(String::new()),
+			meta: Rc::new(LevelMeta::default()),
+			unlock: vec![],
+		};
+
+		let mut parser = LevelParser::new(
+			level_file,
+			level_raw_data,
+			ParseState::default(),
+			enemy_types,
+		);
+		if let Ok(canonical) = fs::canonicalize(level_file) {
+			parser.state.include_stack.push(canonical);
+		}
+		let events = parser.parse_file(Some(&mut level))?;
+
+		// References are resolved once every label is known, to allow forward references
+		let labels: HashMap<&str, u32> = events
+			.iter()
+			.enumerate()
+			.filter_map(|(id, evt)| evt.label.as_deref().map(|label| (label, id as u32)))
+			.collect();
+		for (id, evt) in events.iter().enumerate() {
+			let trigger = match &evt.ref_evt {
+				RefToken::Level => Trigger::Event(None),
+				RefToken::Id(ref_id) => Trigger::Event(Some(*ref_id)),
+				RefToken::Label { label, line, column } => match labels.get(label.as_str()) {
+					Some(ref_id) => Trigger::Event(Some(*ref_id)),
+					None => {
+						return Err(parser.error(
+							*line,
+							*column,
+							ParseErrorKind::UnknownLabel(label.clone()),
+						))
+					},
+				},
+				RefToken::Cleared { wave, line, column } => {
+					if !labels.keys().any(|label| is_in_wave(label, wave)) {
+						return Err(parser.error(
+							*line,
+							*column,
+							ParseErrorKind::UnknownLabel(wave.clone()),
+						));
+					}
+					Trigger::WaveCleared(wave.clone())
+				},
+				RefToken::Condition(trigger) => trigger.clone(),
+			};
+			// Events are all relative, the "absolute" events will be relative to the beginning of the level
+			level.event_list.push(Event {
+				id: id as u32,
+				label: evt.label.clone(),
+				time: None,
+				variant: evt.variant.clone(),
+				trigger: Some((trigger, evt.t)),
+			});
+		}
+		Ok(level)
+	}
+
+	/// Writes the level in the canonical `.hbh` format, which parses back to the same level
+	///
+	/// Included waves and blocks are written as the events they expand to.
+	pub fn to_hbh(&self) -> String {
+		let meta = &self.meta;
+		let mut metadata = vec![];
+		if !self.name.is_empty() {
+			metadata.push(format!("$title {}", self.name));
+		}
+		if let Some(author) = &meta.author {
+			metadata.push(format!("$author {author}"));
+		}
+		if let Some(difficulty) = meta.difficulty {
+			metadata.push(format!("$difficulty {difficulty}"));
+		}
+		if let Some(description) = &meta.description {
+			metadata.push(format!("$description {description}"));
+		}
+		if let Some(music) = &meta.music {
+			metadata.push(format!("$music {music}"));
+		}
+		if let Some(background) = meta.background {
+			metadata.push(format!("$background {}", format_color(background)));
+		}
+		if let Some(par_score) = meta.par_score {
+			metadata.push(format!("$par-score {par_score}"));
+		}
+		if let Some(time_limit) = meta.time_limit {
+			metadata.push(format!("$time-limit {}", format_time(time_limit)));
+		}
+		if let Some(order) = meta.order {
+			metadata.push(format!("$order {order}"));
+		}
+
+		let labels: HashMap<u32, &str> = self
+			.event_list
+			.iter()
+			.filter_map(|evt| evt.label.as_deref().map(|label| (evt.id, label)))
+			.collect();
+		// Bosses are followed by the lines of their phases
+		let mut phase_lines: HashMap<usize, Vec<String>> = HashMap::new();
+		let rows: Vec<[String; 7]> = self
+			.event_list
+			.iter()
+			.map(|evt| {
+				let (trigger, t) = evt
+					.trigger
+					.clone()
+					.unwrap_or((Trigger::Event(None), Duration::ZERO));
+				let ref_evt = match trigger {
+					Trigger::Event(None) => "-".into(),
+					// Labels are easier to read and survive the insertion of events
+					Trigger::Event(Some(ref_id)) => match labels.get(&ref_id) {
+						Some(label) => label.to_string(),
+						None => ref_id.to_string(),
+					},
+					Trigger::WaveCleared(wave) => format!("cleared:{wave}"),
+					Trigger::Score(score) => format!("score:{score}"),
+					Trigger::PlayerHit => "hit".into(),
+					Trigger::EnemiesBelow(n) => format!("enemies-below:{n}"),
+				};
+				let label = evt.label.as_ref().map(|label| format!("#{label}"));
+				match &evt.variant {
+					EventType::_SpawnEnemy(pos, name, path) => [
+						"@spawn-enemy".into(),
+						format!(
+							"{name}{}",
+							path
+								.as_ref()
+								.map_or(String::new(), |path| format!(":{}", path.name)),
+						),
+						format_time(t),
+						pos.x.to_string(),
+						pos.y.to_string(),
+						ref_evt,
+						label.unwrap_or_default(),
+					],
+					EventType::_SpawnBoss(pos, def) => {
+						phase_lines.insert(evt.id as usize, format_phases(def));
+						[
+							"@spawn-boss".into(),
+							def.name.clone(),
+							format_time(t),
+							pos.x.to_string(),
+							pos.y.to_string(),
+							ref_evt,
+							label.map_or("{".into(), |label| format!("{label} {{")),
+						]
+					},
+				}
+			})
+			.collect();
+		let header = ["//", "<var>", "<t>", "<x>", "<y>", "<ref>", "[#label]"].map(String::from);
+		// Text columns are aligned on the left and numbers on the right
+		let mut widths = [0; 7];
+		for row in rows.iter().chain([&header]) {
+			for (width, cell) in widths.iter_mut().zip(row) {
+				*width = cell.chars().count().max(*width);
+			}
+		}
+		let format_row = |row: &[String; 7]| {
+			let [kind, variant, t, x, y, ref_evt, label] = row;
+			let [w_kind, w_variant, w_t, w_x, w_y, w_ref, _] = widths;
+			format!(
+				"{kind:w_kind$} {variant:w_variant$} {t:>w_t$} {x:>w_x$} {y:>w_y$} {ref_evt:w_ref$} {label}"
+			)
+			.trim_end()
+			.to_string()
+		};
+		let mut lines = vec![format_row(&header)];
+		for (i, row) in rows.iter().enumerate() {
+			lines.push(format_row(row));
+			if let Some(phases) = phase_lines.remove(&i) {
+				lines.extend(phases);
+			}
+		}
+
+		// Paths are written once, before the first enemy following them
+		let mut paths: Vec<&EnemyPath> = vec![];
+		for evt in &self.event_list {
+			if let EventType::_SpawnEnemy(_, _, Some(path)) = &evt.variant {
+				if !paths.iter().any(|written| written.name == path.name) {
+					paths.push(path);
+				}
+			}
+		}
+
+		let mut text = String::new();
+		if !metadata.is_empty() {
+			text += "# Metadata\n";
+			metadata
+				.iter()
+				.for_each(|line| text += &format!("{line}\n"));
+		}
+		if !paths.is_empty() {
+			if !text.is_empty() {
+				text += "\n";
+			}
+			text += "# Paths\n";
+			for path in paths {
+				format_path(path)
+					.iter()
+					.for_each(|line| text += &format!("{line}\n"));
+			}
+		}
+		if !rows.is_empty() {
+			if !text.is_empty() {
+				text += "\n";
+			}
+			text += "# Events\n";
+			lines.iter().for_each(|line| text += &format!("{line}\n"));
+		}
+		text
+	}
+}
+
+/// Cells of a line aligned with the lines around it, `None` for the lines written as they are
+///
+/// Column headers (`// <type> <var> ...`) are aligned with the events below them, and the points
+/// of path segments are kept in pairs.
+fn line_cells(line: &str) -> Option<Vec<String>> {
+	let tokens: Vec<&str> = line.split_whitespace().collect();
+	match tokens[..] {
+		["//", header, ..] if header.starts_with('<') => {
+			let mut cells = vec![format!("// {header}")];
+			cells.extend(tokens[2..].iter().map(|token| token.to_string()));
+			Some(cells)
+		},
+		["@line" | "@bezier" | "@spline", ref numbers @ ..] if numbers.len() % 2 == 1 => {
+			let (speed, coords) = numbers.split_last()?;
+			let points = coords.chunks(2).map(|xy| xy.join(" ")).collect::<Vec<_>>();
+			Some(vec![format!(
+				"{} {}  {speed}",
+				tokens[0],
+				points.join("  ")
+			)])
+		},
+		[first, ..] if first.starts_with('@') => {
+			Some(tokens.iter().map(|token| token.to_string()).collect())
+		},
+		_ => None,
+	}
+}
+
+/// Lines sharing columns, the events share the columns of the header comment above them
+fn column_group(cells: &[String]) -> &str {
+	match cells[0].as_str() {
+		"@spawn-enemy" | "@spawn-boss" | "@use-wave" => "event",
+		header if header.starts_with("//") => "event",
+		first => first.split_whitespace().next().unwrap_or_default(),
+	}
+}
+
+/// Aligns the columns of consecutive lines, numbers on the right and text on the left
+fn align_rows(indent: &str, rows: &[Vec<String>]) -> Vec<String> {
+	let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+	let mut widths = vec![0; columns];
+	// Header comments don't decide which columns hold numbers
+	let mut numeric = vec![true; columns];
+	for row in rows {
+		for (i, cell) in row.iter().enumerate() {
+			widths[i] = cell.chars().count().max(widths[i]);
+			if !row[0].starts_with("//") && cell.parse::<f64>().is_err() {
+				numeric[i] = false;
+			}
+		}
+	}
+	rows
+		.iter()
+		.map(|row| {
+			let cells: Vec<String> = row
+				.iter()
+				.enumerate()
+				.map(|(i, cell)| {
+					if i > 0 && numeric[i] {
+						format!("{cell:>w$}", w = widths[i])
+					} else {
+						format!("{cell:w$}", w = widths[i])
+					}
+				})
+				.collect();
+			format!("{indent}{}", cells.join(" ").trim_end())
+		})
+		.collect()
+}
+
+/// Normalizes the layout of a `.hbh` or `.hbw` file line by line: blocks are indented with tabs,
+/// tokens are separated by one space and the columns of consecutive lines of events are aligned
+///
+/// Unlike `Level::to_hbh`, comments, includes, waves and blocks are kept as they are written.
+pub fn format_hbh(source: &str) -> String {
+	let mut text = String::new();
+	let mut depth = 0;
+	// Consecutive lines sharing columns, with their cells and indentation
+	let mut rows: Vec<(&str, Vec<String>)> = vec![];
+	let mut rows_indent = String::new();
+	let flush = |text: &mut String, rows: &mut Vec<(&str, Vec<String>)>, indent: &str| {
+		match &rows[..] {
+			// A header alone has nothing to be aligned with
+			[(header, _)] if header.starts_with("//") => *text += &format!("{indent}{header}\n"),
+			_ => {
+				let cells: Vec<Vec<String>> = rows.drain(..).map(|(_, cells)| cells).collect();
+				for row in align_rows(indent, &cells) {
+					*text += &format!("{row}\n");
+				}
+			},
+		}
+		rows.clear();
+	};
+	for line in source.lines() {
+		let line = line.trim();
+		if line == "}" {
+			depth = usize::max(depth, 1) - 1;
+		}
+		let indent = "\t".repeat(depth);
+		let cells = line_cells(line);
+		let same_group = match (rows.last(), &cells) {
+			(Some((_, last)), Some(cells)) => {
+				rows_indent == indent && column_group(last) == column_group(cells)
+			},
+			_ => false,
+		};
+		if !rows.is_empty() && !same_group {
+			flush(&mut text, &mut rows, &rows_indent);
+		}
+		if let Some(cells) = cells {
+			rows_indent = indent;
+			rows.push((line, cells));
+			if line.starts_with('@') && line.ends_with('{') {
+				depth += 1;
+			}
+		} else if line.starts_with('$') {
+			// Metadata values are text, like descriptions, they are kept as they are
+			let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+			text += format!("{indent}{key} {}", value.trim()).trim_end();
+			text += "\n";
+		} else if line.is_empty() {
+			text += "\n";
+		} else {
+			text += &format!("{indent}{line}\n");
+		}
+	}
+	if !rows.is_empty() {
+		flush(&mut text, &mut rows, &rows_indent);
+	}
+	text
+}
+
+/// Writes the `$hitbox` and the `@phase` lines of a boss, aligned, and the closing `}`
+fn format_phases(def: &BossDef) -> Vec<String> {
+	let rows: Vec<[String; 5]> = def
+		.phases
+		.iter()
+		.map(|phase| {
+			[
+				phase.hp.to_string(),
+				phase.timeout.map_or("-".into(), format_time),
+				match phase.movement {
+					BossMovement::Hover => "hover",
+					BossMovement::Sweep => "sweep",
+					BossMovement::Chase => "chase",
+				}
+				.into(),
+				match phase.pattern {
+					ShotPattern::Down => "down",
+					ShotPattern::Aimed => "aimed",
+					ShotPattern::Spread => "spread",
+					ShotPattern::Ring => "ring",
+				}
+				.into(),
+				format_time(phase.fire_interval),
+			]
+		})
+		.collect();
+	let mut widths = [0; 5];
+	for row in rows.iter() {
+		for (width, cell) in widths.iter_mut().zip(row) {
+			*width = cell.chars().count().max(*width);
+		}
+	}
+	let [w_hp, w_timeout, w_movement, w_pattern, _] = widths;
+	let hitbox = def
+		.hitbox
+		.map(|hitbox| format!("\t$hitbox {}", format_shape(&hitbox)));
+	let mut lines: Vec<String> = hitbox
+		.into_iter()
+		.chain(rows.iter().map(|[hp, timeout, movement, pattern, interval]| {
+			format!("\t@phase {hp:>w_hp$} {timeout:>w_timeout$} {movement:w_movement$} {pattern:w_pattern$} {interval}")
+		}))
+		.collect();
+	lines.push("}".into());
+	lines
+}
+
+/// Writes the `@path` block of a path
+fn format_path(path: &EnemyPath) -> Vec<String> {
+	let mut lines = vec![format!("@path {} {{", path.name)];
+	for segment in &path.segments {
+		let points: Vec<String> = segment
+			.points()
+			.iter()
+			.map(|point| format!("{} {}", point.x, point.y))
+			.collect();
+		lines.push(match segment {
+			PathSegment::Hold(t) => format!("\t@hold {}", format_time(*t)),
+			segment => {
+				let key = match segment {
+					PathSegment::Line { .. } => "line",
+					PathSegment::Bezier { .. } => "bezier",
+					_ => "spline",
+				};
+				let speed = segment.speed().unwrap_or_default();
+				format!("\t@{key} {}  {speed}", points.join("  "))
+			},
+		});
+	}
+	lines.push("}".into());
+	lines
+}
+
+fn file_name(path: &Path) -> String {
+	path
+		.file_name()
+		.unwrap_or_default()
+		.to_string_lossy()
+		.into_owned()
+}
+
+/// Writes a color with its palette name if it has one
+fn format_color(color: [u8; 4]) -> String {
+	match BACKGROUND_PALETTE
+		.iter()
+		.find(|(_, palette_color)| *palette_color == color)
+	{
+		Some((name, _)) => name.to_string(),
+		None => format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2]),
+	}
+}
+
+/// Writes the exact number of seconds of a duration, without trailing zeros
+fn format_time(t: Duration) -> String {
+	match t.subsec_nanos() {
+		0 => t.as_secs().to_string(),
+		nanos => format!("{}.{nanos:09}", t.as_secs())
+			.trim_end_matches('0')
+			.into(),
+	}
+}
+
+/// Parses a `#rrggbb` color or the name of a background of the palette
+fn parse_color(token: &str) -> Option<[u8; 4]> {
+	if let Some(hex) = token.strip_prefix('#') {
+		if hex.len() != 6 || !hex.is_ascii() {
+			return None;
+		}
+		let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok();
+		return Some([channel(0)?, channel(1)?, channel(2)?, 0xff]);
+	}
+	BACKGROUND_PALETTE
+		.iter()
+		.find(|(name, _)| *name == token)
+		.map(|(_, color)| *color)
+}
+
+/// Parses a time in seconds, from 0 to `MAX_TIME`
+pub fn parse_time(tokens: &mut LineTokens) -> Result<Duration, LevelParseError> {
+	parse_duration(tokens, "time in seconds (0 to 86400)", Duration::ZERO)
+}
+
+/// Parses the time between two repetitions of an action, more than 0 and up to `MAX_TIME`
+pub fn parse_interval(tokens: &mut LineTokens) -> Result<Duration, LevelParseError> {
+	parse_duration(
+		tokens,
+		"interval in seconds (more than 0, up to 86400)",
+		Duration::from_nanos(1),
+	)
+}
+
+fn parse_duration(
+	tokens: &mut LineTokens,
+	expected: &'static str,
+	min: Duration,
+) -> Result<Duration, LevelParseError> {
+	let (col, token) = tokens.next(expected)?;
+	let t = token
+		.parse::<f64>()
+		.ok()
+		.and_then(|t| Duration::try_from_secs_f64(t).ok());
+	match t {
+		Some(t) if t >= min && t <= MAX_TIME => Ok(t),
+		_ => Err(tokens.error(
+			col,
+			ParseErrorKind::InvalidToken { expected, found: token.into() },
+		)),
+	}
+}
+
+/// Problem in the graph formed by the events and their references
+#[derive(Clone, Debug)]
+pub enum EventGraphError {
+	/// The event references an id that doesn't exist in the level
+	DanglingRef { event: u32, ref_id: u32 },
+	/// Events referencing each other in a loop, none of them can fire
+	Cycle(Vec<u32>),
+	/// The event waits on an event that can never fire
+	Unreachable { event: u32, blocked_by: u32 },
+	/// The event waits for a wave to be cleared but no event has its label
+	UnknownWave { event: u32, wave: String },
+}
+
+impl fmt::Display for EventGraphError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EventGraphError::DanglingRef { event, ref_id } => {
+				write!(
+					f,
+					"event {event} references event {ref_id} which doesn't exist"
+				)
+			},
+			EventGraphError::Cycle(events) => {
+				let events: Vec<String> = events.iter().map(|id| id.to_string()).collect();
+				write!(
+					f,
+					"events {} reference each other in a cycle",
+					events.join(" -> ")
+				)
+			},
+			EventGraphError::Unreachable { event, blocked_by } => {
+				write!(
+					f,
+					"event {event} never fires because event {blocked_by} never fires"
+				)
+			},
+			EventGraphError::UnknownWave { event, wave } => {
+				write!(
+					f,
+					"event {event} waits for wave '{wave}' but no event has this label"
+				)
+			},
+		}
+	}
+}
+
+/// Event of a valid level that may not fire
+#[derive(Clone, Debug)]
+pub enum EventGraphWarning {
+	/// The event waits for a score or for the player to be hit, it is cancelled if the level is
+	/// cleared before
+	Cancellable { event: u32, condition: Trigger },
+}
+
+impl fmt::Display for EventGraphWarning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EventGraphWarning::Cancellable { event, condition } => {
+				let condition = match condition {
+					Trigger::Score(score) => format!("score:{score}"),
+					Trigger::PlayerHit => "hit".into(),
+					condition => format!("{condition:?}"),
+				};
+				write!(
+					f,
+					"event {event} waits for '{condition}', it is cancelled if the level is cleared before"
+				)
+			},
+		}
+	}
+}
+
+/// Result of the validation of the events of a level
+#[derive(Clone, Debug)]
+pub struct EventGraphReport {
+	/// Earliest time after the beginning of the level at which each event fires, indexed by
+	/// event id, `None` if the event never fires
+	///
+	/// Conditions are assumed to happen as soon as possible, waves are cleared when their last
+	/// event fires.
+	pub fire_times: Vec<Option<Duration>>,
+	pub errors: Vec<EventGraphError>,
+	pub warnings: Vec<EventGraphWarning>,
+}
+
+impl EventGraphReport {
+	/// Time at which the last event fires
+	pub fn duration(&self) -> Duration {
+		self
+			.fire_times
+			.iter()
+			.flatten()
+			.max()
+			.copied()
+			.unwrap_or_default()
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Visit {
+	NotVisited,
+	InProgress,
+	Done(Option<Duration>),
+}
+
+impl Level {
+	/// Builds the dependency graph of the events, reports the references that can't be resolved
+	/// and computes when each event fires
+	pub fn validate(&self) -> EventGraphReport {
+		let n_events = self.event_list.len();
+		let mut errors = vec![];
+		// Events with their own error, they never fire but aren't reported as unreachable
+		let mut failed = vec![false; n_events];
+		// Events each event waits for, the other triggers don't depend on events
+		let dependencies: Vec<Vec<u32>> = self
+			.event_list
+			.iter()
+			.enumerate()
+			.map(|(id, evt)| match &evt.trigger {
+				Some((Trigger::Event(Some(ref_id)), _)) => vec![*ref_id],
+				Some((Trigger::WaveCleared(wave), _)) => {
+					let ids: Vec<u32> = self
+						.event_list
+						.iter()
+						.filter(|e| e.in_wave(wave))
+						.map(|e| e.id)
+						.collect();
+					if ids.is_empty() {
+						errors
+							.push(EventGraphError::UnknownWave { event: id as u32, wave: wave.clone() });
+						failed[id] = true;
+					}
+					ids
+				},
+				_ => vec![],
+			})
+			.collect();
+
+		let mut visits = vec![Visit::NotVisited; n_events];
+		for start in 0..n_events {
+			if !matches!(visits[start], Visit::NotVisited) {
+				continue;
+			}
+			// Depth first search, with the index of the next dependency to visit of each event
+			visits[start] = Visit::InProgress;
+			let mut stack = vec![(start, 0)];
+			while let Some((current, next)) = stack.last_mut() {
+				let current = *current;
+				if let Some(&ref_id) = dependencies[current].get(*next) {
+					*next += 1;
+					if ref_id as usize >= n_events {
+						errors.push(EventGraphError::DanglingRef { event: current as u32, ref_id });
+						failed[current] = true;
+						continue;
+					}
+					match visits[ref_id as usize] {
+						Visit::NotVisited => {
+							visits[ref_id as usize] = Visit::InProgress;
+							stack.push((ref_id as usize, 0));
+						},
+						Visit::InProgress => {
+							let cycle_begin = stack
+								.iter()
+								.position(|&(e, _)| e == ref_id as usize)
+								.unwrap();
+							let cycle: Vec<u32> = stack[cycle_begin..]
+								.iter()
+								.map(|&(e, _)| e as u32)
+								.collect();
+							for &e in cycle.iter() {
+								failed[e as usize] = true;
+							}
+							errors.push(EventGraphError::Cycle(cycle));
+						},
+						Visit::Done(_) => {},
+					}
+					continue;
+				}
+
+				// Every dependency is computed, the event fires after the last of them
+				stack.pop();
+				if failed[current] {
+					visits[current] = Visit::Done(None);
+					continue;
+				}
+				let mut time = Some(Duration::ZERO);
+				for &ref_id in dependencies[current].iter() {
+					match visits[ref_id as usize] {
+						Visit::Done(Some(ref_time)) => time = time.map(|t| t.max(ref_time)),
+						_ => {
+							errors.push(EventGraphError::Unreachable {
+								event: current as u32,
+								blocked_by: ref_id,
+							});
+							time = None;
+							break;
+						},
+					}
+				}
+				let offset = match &self.event_list[current].trigger {
+					Some((_, offset)) => *offset,
+					None => Duration::ZERO,
+				};
+				visits[current] = Visit::Done(time.map(|t| t + offset));
+			}
+		}
+
+		let fire_times = visits
+			.iter()
+			.map(|visit| match visit {
+				Visit::Done(time) => *time,
+				_ => None,
+			})
+			.collect();
+		// The other conditions happen at the latest when the last enemies are gone
+		let warnings = self
+			.event_list
+			.iter()
+			.filter_map(|evt| match &evt.trigger {
+				Some((condition @ (Trigger::Score(_) | Trigger::PlayerHit), _)) => {
+					Some(EventGraphWarning::Cancellable { event: evt.id, condition: condition.clone() })
+				},
+				_ => None,
+			})
+			.collect();
+		EventGraphReport { fire_times, errors, warnings }
+	}
+}
+
+/// Reason why a level couldn't be loaded
+#[derive(Clone, Debug)]
+pub enum LevelError {
+	Parse(LevelParseError),
+	Events(Vec<EventGraphError>),
+}
+
+impl fmt::Display for LevelError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LevelError::Parse(err) => write!(f, "{err}"),
+			LevelError::Events(errors) => {
+				let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+				write!(f, "{}", errors.join("\n"))
+			},
+		}
+	}
+}
+
+/// Level file that failed to load, kept to be listed in the level selection
+#[derive(Clone, Debug)]
+pub struct InvalidLevel {
+	pub name: String,
+	pub error: LevelError,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::enemy::ENEMY_DIR;
+
+	fn parse(content: &str) -> Result<Level, LevelParseError> {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		Level::parse_hbh(Path::new("levels/test.hbh"), content, 0, &enemy_types)
+	}
+
+	fn parse_data(content: &str) -> Result<Level, LevelParseError> {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		Level::parse_data(Path::new("levels/test.hbh.ron"), content, 0, &enemy_types)
+	}
+
+	/// Line, column and kind of the error of an invalid level
+	fn parse_error(content: &str) -> (usize, usize, ParseErrorKind) {
+		let err = parse(content).expect_err("the level should be invalid");
+		(err.line, err.column, err.kind)
+	}
+
+	#[test]
+	fn invalid_token_is_located() {
+		let (line, column, kind) = parse_error("$title Test\n\n@spawn-enemy basic x 400 -25 -\n");
+		assert_eq!((line, column), (3, 20));
+		assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == "x"));
+	}
+
+	#[test]
+	fn missing_token_is_at_end_of_line() {
+		let (line, column, kind) = parse_error("@spawn-enemy basic 1 400");
+		assert_eq!((line, column), (1, 25));
+		assert!(matches!(
+			kind,
+			ParseErrorKind::MissingToken { expected: "y coordinate" }
+		));
+	}
+
+	#[test]
+	fn trailing_token_is_located() {
+		let (line, column, kind) = parse_error("@spawn-enemy basic 1 400 -25 - #a b");
+		assert_eq!((line, column), (1, 35));
+		assert!(matches!(kind, ParseErrorKind::TrailingToken(token) if token == "b"));
+	}
+
+	#[test]
+	fn unknown_names_are_located() {
+		let (line, column, kind) = parse_error("@spawn-enemy ghost 1 400 -25 -");
+		assert_eq!((line, column), (1, 14));
+		assert!(matches!(kind, ParseErrorKind::UnknownEnemy(name) if name == "ghost"));
+
+		let (line, column, kind) =
+			parse_error("@spawn-enemy basic 1 400 -25 -\n@spawn-enemy basic 1 400 -25 #nope");
+		assert_eq!((line, column), (2, 30));
+		assert!(matches!(kind, ParseErrorKind::UnknownLabel(label) if label == "nope"));
+	}
+
+	#[test]
+	fn unclosed_block_points_at_its_opening() {
+		let (line, column, kind) = parse_error("\n@repeat 3 1 {\n\t@spawn-enemy basic 0 400 -25 -\n");
+		assert_eq!((line, column), (2, 13));
+		assert!(matches!(kind, ParseErrorKind::UnclosedBlock));
+		let (line, _, kind) = parse_error("@spawn-enemy basic 0 400 -25 -\n}");
+		assert_eq!(line, 2);
+		assert!(matches!(kind, ParseErrorKind::UnmatchedBlockEnd));
+	}
+
+	#[test]
+	fn out_of_range_times_are_invalid() {
+		for time in ["1e30", "-1", "NaN", "inf", "86401"] {
+			let (line, column, kind) = parse_error(&format!("@spawn-enemy basic {time} 400 -25 -"));
+			assert_eq!((line, column), (1, 20), "{time}");
+			assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == time));
+		}
+		assert!(parse("@spawn-enemy basic 86400 400 -25 -").is_ok());
+	}
+
+	#[test]
+	fn repeat_count_is_bounded() {
+		for count in ["0", "1001", "1e9"] {
+			let level = format!("@repeat {count} 1 {{\n\t@spawn-enemy basic 0 400 -25 -\n}}");
+			let (line, column, kind) = parse_error(&level);
+			assert_eq!((line, column), (1, 9), "{count}");
+			assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == count));
+		}
+		// Nested blocks multiply their counts
+		let level = "@repeat 1000 0 {\n\t@repeat 11 0 {\n\t\t@spawn-enemy basic 0 400 -25 -\n\t}\n}";
+		let (line, column, _) = parse_error(level);
+		assert_eq!((line, column), (1, 9));
+		let level = "@repeat 1000 0 {\n\t@repeat 10 0 {\n\t\t@spawn-enemy basic 0 400 -25 -\n\t}\n}";
+		assert_eq!(parse(level).unwrap().event_list.len(), 10_000);
+	}
+
+	#[test]
+	fn metadata_is_parsed() {
+		let level = parse(
+			"$difficulty 5\n\
+			$background #1a2B3c\n\
+			$par-score 1500\n\
+			$time-limit 90.5\n\
+			$order -2\n\
+			@spawn-enemy basic 0 400 -25 -",
+		)
+		.unwrap();
+		let meta = &level.meta;
+		assert_eq!(meta.difficulty, Some(5));
+		assert_eq!(meta.background, Some([0x1a, 0x2b, 0x3c, 0xff]));
+		assert_eq!(meta.par_score, Some(1500));
+		assert_eq!(meta.time_limit, secs(90.5));
+		assert_eq!(meta.order, Some(-2));
+		for (name, color) in BACKGROUND_PALETTE {
+			let level = parse(&format!(
+				"$background {name}\n@spawn-enemy basic 0 400 -25 -"
+			));
+			assert_eq!(level.unwrap().meta.background, Some(color), "{name}");
+		}
+		// A time limit of 0 is no limit
+		let level = parse("$difficulty 1\n$time-limit 0\n@spawn-enemy basic 0 400 -25 -").unwrap();
+		assert_eq!(
+			(level.meta.difficulty, level.meta.time_limit),
+			(Some(1), None)
+		);
+	}
+
+	#[test]
+	fn invalid_metadata_is_located() {
+		let cases = [
+			("$difficulty 0", 13, "0"),
+			("$difficulty 6", 13, "6"),
+			("$background #12345", 13, "#12345"),
+			("$background #12345g", 13, "#12345g"),
+			("$background #éé345", 13, "#éé345"),
+			("$background noon", 13, "noon"),
+			("$par-score -1", 12, "-1"),
+			("$par-score 1.5", 12, "1.5"),
+			("$time-limit -1", 13, "-1"),
+			("$time-limit NaN", 13, "NaN"),
+			("$time-limit 86401", 13, "86401"),
+			("$order 1.5", 8, "1.5"),
+			("$order first", 8, "first"),
+		];
+		for (meta, column, token) in cases {
+			let (line, err_column, kind) =
+				parse_error(&format!("{meta}\n@spawn-enemy basic 0 400 -25 -"));
+			assert_eq!((line, err_column), (1, column), "{meta}");
+			assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == token));
+		}
+	}
+
+	#[test]
+	fn repeat_offsets_are_finite() {
+		for offset in ["x+=NaN", "y-=inf", "x+=1e39", "y+=", "x+=a"] {
+			let level = format!("@repeat 3 1 y+=1 {offset} {{\n\t@spawn-enemy basic 0 400 -25 -\n}}");
+			let (line, column, kind) = parse_error(&level);
+			assert_eq!((line, column), (1, 18), "{offset}");
+			assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == offset));
+		}
+		let level = parse("@repeat 3 1 x+=1.5 y-=2 {\n\t@spawn-enemy basic 0 400 -25 -\n}").unwrap();
+		assert_eq!(level.event_list.len(), 3);
+	}
+
+	#[test]
+	fn non_finite_coordinates_are_rejected() {
+		let events = [
+			"@spawn-enemy basic 1 {x} {y} -",
+			"@spawn-boss warden 1 {x} {y} - {\n\t@phase 10 - hover down 1\n}",
+			"@wave w {\n\t@spawn-enemy basic 0 0 0 -\n}\n@use-wave w 1 {x} {y} -",
+		];
+		for event in events {
+			let line = event.lines().position(|line| line.contains("{x}")).unwrap() + 1;
+			let column = event.lines().nth(line - 1).unwrap().find("{x}").unwrap() + 1;
+			for value in ["NaN", "inf", "-inf", "1e39"] {
+				for (x, y, column) in [(value, "0", column), ("0", value, column + 2)] {
+					let level = event.replace("{x}", x).replace("{y}", y);
+					let (err_line, err_column, kind) = parse_error(&level);
+					assert_eq!((err_line, err_column), (line, column), "{level}");
+					assert!(
+						matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == value)
+					);
+				}
+			}
+		}
+	}
+
+	fn secs(t: f64) -> Option<Duration> {
+		Some(Duration::from_secs_f64(t))
+	}
+
+	#[test]
+	fn fire_times_follow_references() {
+		let level = parse(
+			"@spawn-enemy basic 1 400 -25 - #a\n\
+			@spawn-enemy basic 2 400 -25 a\n\
+			@repeat 3 1 #wave {\n\
+				@spawn-enemy basic 0.5 400 -25 a\n\
+			}\n\
+			@spawn-enemy basic 1 400 -25 cleared:wave\n\
+			@spawn-enemy basic 3 400 -25 score:100\n",
+		)
+		.unwrap();
+		let report = level.validate();
+		assert!(report.errors.is_empty(), "{:?}", report.errors);
+		let expected = [1., 3., 1.5, 2.5, 3.5, 4.5, 3.];
+		assert_eq!(report.fire_times, expected.map(secs));
+		assert_eq!(report.duration(), Duration::from_secs_f64(4.5));
+	}
+
+	#[test]
+	fn dangling_refs_and_cycles_are_reported() {
+		let level = parse(
+			"@spawn-enemy basic 1 400 -25 7\n\
+			@spawn-enemy basic 1 400 -25 #c #b\n\
+			@spawn-enemy basic 1 400 -25 #b #c\n\
+			@spawn-enemy basic 1 400 -25 #c\n\
+			@spawn-enemy basic 1 400 -25 -\n",
+		)
+		.unwrap();
+		let report = level.validate();
+		assert_eq!(report.fire_times, [None, None, None, None, secs(1.)]);
+		let errors: Vec<String> = report.errors.iter().map(|err| err.to_string()).collect();
+		assert_eq!(
+			errors,
+			[
+				"event 0 references event 7 which doesn't exist",
+				"events 1 -> 2 reference each other in a cycle",
+				"event 3 never fires because event 2 never fires",
+			]
+		);
+	}
+
+	#[test]
+	fn long_chains_dont_overflow_the_stack() {
+		let mut content = "@spawn-enemy basic 1 400 -25 -\n".to_string();
+		for id in 0..5000 {
+			content.push_str(&format!("@spawn-enemy basic 1 400 -25 {id}\n"));
+		}
+		let report = parse(&content).unwrap().validate();
+		assert!(report.errors.is_empty());
+		assert_eq!(report.duration(), Duration::from_secs(5001));
+	}
+
+	#[test]
+	fn unknown_waves_are_reported() {
+		let mut level =
+			parse("@spawn-enemy basic 1 400 -25 -\n@spawn-enemy basic 1 400 -25 0\n").unwrap();
+		level.event_list[0].trigger = Some((Trigger::WaveCleared("ghosts".into()), Duration::ZERO));
+		let report = level.validate();
+		// The event never fires, nor the events waiting for it
+		assert!(matches!(
+			&report.errors[..],
+			[
+				EventGraphError::UnknownWave { event: 0, wave },
+				EventGraphError::Unreachable { event: 1, blocked_by: 0 },
+			] if wave == "ghosts"
+		));
+		assert_eq!(report.fire_times, [None, None]);
+	}
+
+	/// Position, delay and label of each event
+	fn spawns(level: &Level) -> Vec<((f32, f32), f64, Option<&str>)> {
+		level
+			.event_list
+			.iter()
+			.map(|evt| {
+				let EventType::_SpawnEnemy(pos, ..) = &evt.variant else {
+					panic!("not an enemy");
+				};
+				let t = evt.trigger.as_ref().unwrap().1.as_secs_f64();
+				((pos.x, pos.y), t, evt.label.as_deref())
+			})
+			.collect()
+	}
+
+	#[test]
+	fn repeat_blocks_expand() {
+		let level = parse(
+			"@spawn-enemy basic 1 0 0 - #first\n\
+			@repeat 3 0.5 x+=10 y-=5 #stream {\n\
+				@spawn-enemy basic 1 100 0 first\n\
+				@repeat 2 2 {\n\
+					@spawn-enemy basic 0 0 0 -\n\
+				}\n\
+			}\n\
+			@spawn-enemy basic 0 0 0 stream.8",
+		)
+		.unwrap();
+		let expected = [
+			((0., 0.), 1., Some("first")),
+			((100., 0.), 1., Some("stream.0")),
+			((0., 0.), 0., Some("stream.1")),
+			((0., 0.), 2., Some("stream.2")),
+			((110., -5.), 1.5, Some("stream.3")),
+			((10., -5.), 0.5, Some("stream.4")),
+			((10., -5.), 2.5, Some("stream.5")),
+			((120., -10.), 2., Some("stream.6")),
+			((20., -10.), 1., Some("stream.7")),
+			((20., -10.), 3., Some("stream.8")),
+			((0., 0.), 0., None),
+		];
+		assert_eq!(spawns(&level), expected);
+		let triggers: Vec<&Trigger> = level
+			.event_list
+			.iter()
+			.map(|evt| &evt.trigger.as_ref().unwrap().0)
+			.collect();
+		// Every copy references the event outside of the block
+		for id in [1, 4, 7] {
+			assert!(matches!(triggers[id], Trigger::Event(Some(0))));
+		}
+		assert!(matches!(triggers[10], Trigger::Event(Some(9))));
+	}
+
+	#[test]
+	fn blocks_cant_reference_themselves() {
+		let (line, column, kind) =
+			parse_error("@spawn-enemy basic 0 0 0 -\n@repeat 2 1 {\n\t@spawn-enemy basic 0 0 0 0\n}");
+		assert_eq!((line, column), (3, 27));
+		assert!(matches!(kind, ParseErrorKind::RefInBlock(found) if found == "0"));
+
+		for target in ["s.0", "cleared:s"] {
+			let level = format!(
+				"@repeat 2 1 #s {{\n\t@spawn-enemy basic 0 0 0 -\n\t@spawn-enemy basic 0 0 0 {target}\n}}"
+			);
+			let (line, column, kind) = parse_error(&level);
+			assert_eq!((line, column), (1, 13), "{target}");
+			assert!(matches!(kind, ParseErrorKind::RefInBlock(_)));
+		}
+	}
+
+	#[test]
+	fn waves_expand_at_each_use() {
+		let level = parse(
+			"@spawn-enemy basic 3 0 0 - #lead\n\
+			@wave w {\n\
+				@spawn-enemy basic 1 10 20 -\n\
+				@spawn-enemy basic 2 30 40 lead\n\
+			}\n\
+			@use-wave w 0 0 0 - #first\n\
+			@use-wave w 5 100 -10 first.1 #second",
+		)
+		.unwrap();
+		let expected = [
+			((0., 0.), 3., Some("lead")),
+			((10., 20.), 1., Some("first.0")),
+			((30., 40.), 2., Some("first.1")),
+			((110., 10.), 6., Some("second.0")),
+			((130., 30.), 2., Some("second.1")),
+		];
+		assert_eq!(spawns(&level), expected);
+		let triggers: Vec<&Trigger> = level
+			.event_list
+			.iter()
+			.map(|evt| &evt.trigger.as_ref().unwrap().0)
+			.collect();
+		assert!(matches!(
+			triggers[..],
+			[
+				Trigger::Event(None),
+				Trigger::Event(None),
+				Trigger::Event(Some(0)),
+				Trigger::Event(Some(2)),
+				Trigger::Event(Some(0))
+			]
+		));
+	}
+
+	#[test]
+	fn waves_cant_reference_their_copies() {
+		let wave = "@wave w {\n\t@spawn-enemy basic 1 0 0 -\n\t@spawn-enemy basic 1 0 0 first.0\n}\n";
+		let (line, column, kind) = parse_error(&format!("{wave}@use-wave w 0 0 0 - #first"));
+		assert_eq!((line, column), (5, 21));
+		assert!(matches!(kind, ParseErrorKind::RefInBlock(found) if found == "first.0"));
+
+		let (line, column, kind) = parse_error("@wave w {\n\t@spawn-enemy basic 1 0 0 3\n}");
+		assert_eq!((line, column), (2, 27));
+		assert!(matches!(kind, ParseErrorKind::RefInBlock(found) if found == "3"));
+	}
+
+	/// Level and wave files in the levels directory
+	fn shipped_files(extension: &str) -> Vec<PathBuf> {
+		let mut files = vec![];
+		for dir in ["levels", "levels/waves"] {
+			for entry in fs::read_dir(dir).unwrap() {
+				let path = entry.unwrap().path();
+				if path.extension().is_some_and(|ext| ext == extension) {
+					files.push(path);
+				}
+			}
+		}
+		files.sort();
+		files
+	}
+
+	#[test]
+	fn to_hbh_parses_back_to_the_same_level() {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		for path in shipped_files("hbh") {
+			let level = Level::load(&path, 0, &enemy_types).unwrap();
+			let text = level.to_hbh();
+			let parsed = Level::parse_hbh(&path, &text, 0, &enemy_types).unwrap();
+			let events = format!("{:?}", level.event_list);
+			assert_eq!(
+				format!("{:?}", parsed.event_list),
+				events,
+				"{}",
+				path.display()
+			);
+			assert_eq!(format!("{:?}", parsed.meta), format!("{:?}", level.meta));
+			assert_eq!(parsed.to_hbh(), text, "{}", path.display());
+		}
+	}
+
+	#[test]
+	fn formatting_keeps_the_structure() {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		let files = [shipped_files("hbh"), shipped_files("hbw")].concat();
+		assert!(files.len() >= 4);
+		for path in files {
+			let source = fs::read_to_string(&path).unwrap();
+			let formatted = format_hbh(&source);
+			assert_eq!(format_hbh(&formatted), formatted, "{}", path.display());
+			// Comments, blocks and includes are kept, on the same lines
+			assert_eq!(formatted.lines().count(), source.lines().count());
+			for (line, formatted_line) in source.lines().zip(formatted.lines()) {
+				let keyword = |line: &str| line.split_whitespace().next().map(str::to_string);
+				assert_eq!(keyword(formatted_line), keyword(line));
+			}
+			let level = Level::load(&path, 0, &enemy_types).unwrap();
+			let parsed = Level::parse_hbh(&path, &formatted, 0, &enemy_types).unwrap();
+			let events = format!("{:?}", level.event_list);
+			assert_eq!(
+				format!("{:?}", parsed.event_list),
+				events,
+				"{}",
+				path.display()
+			);
+		}
+	}
+
+	#[test]
+	fn formatting_aligns_and_indents() {
+		let source = "$title   Test\n\
+			// <type> <var> <t> <x> <y> <ref>\n\
+			@spawn-enemy basic 1 400 -25 -\n\
+			@spawn-enemy sniper 10 50 -25 -\n\
+			\n\
+			@repeat 2 1 {\n\
+			  @spawn-enemy basic 0 0 0 -\n\
+			// inside\n\
+			}\n";
+		let expected = "$title Test\n\
+			// <type>    <var>  <t> <x> <y> <ref>\n\
+			@spawn-enemy basic    1 400 -25 -\n\
+			@spawn-enemy sniper  10  50 -25 -\n\
+			\n\
+			@repeat 2 1 {\n\
+			\t@spawn-enemy basic 0 0 0 -\n\
+			\t// inside\n\
+			}\n";
+		assert_eq!(format_hbh(source), expected);
+	}
+
+	#[test]
+	fn data_levels_match_hbh() {
+		let enemy_types = EnemyTypes::load(Path::new(ENEMY_DIR)).unwrap();
+		let level = Level::load(Path::new("levels/level1.hbh"), 0, &enemy_types).unwrap();
+		for file in ["tests/data/level1.hbh.ron", "tests/data/level1.hbh.json"] {
+			let data = Level::load(Path::new(file), 0, &enemy_types).unwrap();
+			assert_eq!(data.name, level.name, "{file}");
+			assert_eq!(
+				format!("{:?}", data.meta),
+				format!("{:?}", level.meta),
+				"{file}"
+			);
+			let events = format!("{:?}", level.event_list);
+			assert_eq!(format!("{:?}", data.event_list), events, "{file}");
+		}
+	}
+
+	#[test]
+	fn data_events_without_trigger_start_with_the_level() {
+		let level =
+			parse_data("(event_list: [(variant: SpawnEnemy((x: 0.0, y: 0.0), \"basic\", None))])")
+				.unwrap();
+		assert_eq!(
+			level.event_list[0].trigger,
+			Some((Trigger::Event(None), Duration::ZERO))
+		);
+		assert_eq!(level.validate().fire_times, [Some(Duration::ZERO)]);
+	}
+
+	const BOSS: &str = "@spawn-boss warden 1 640 -50 - {\n\
+		\t$hitbox capsule 0 10 30\n\
+		\t@phase 40 - hover aimed 0.5\n\
+		}\n";
+
+	#[test]
+	fn boss_hitbox_is_parsed_and_written() {
+		let level = parse(BOSS).unwrap();
+		let EventType::_SpawnBoss(_, def) = &level.event_list[0].variant else {
+			panic!("not a boss: {:?}", level.event_list[0].variant);
+		};
+		assert!(matches!(
+			def.hitbox,
+			Some(Shape::Capsule { half, radius: 30. }) if half == Vector2::new(0., 10.)
+		));
+		let text = level.to_hbh();
+		assert!(
+			text.contains("\n\t$hitbox capsule 0 10 30\n\t@phase 40"),
+			"{text}"
+		);
+		assert_eq!(parse(&text).unwrap().to_hbh(), text);
+		// Without hitbox, the boss is hit on its whole sprite
+		let level = parse(&BOSS.replace("\t$hitbox capsule 0 10 30\n", "")).unwrap();
+		assert!(!level.to_hbh().contains("$hitbox"));
+	}
+
+	#[test]
+	fn invalid_boss_hitboxes_are_rejected() {
+		let (line, column, kind) = parse_error(&BOSS.replace("capsule 0 10 30", "circle -4"));
+		assert_eq!((line, column), (2, 17));
+		assert!(matches!(kind, ParseErrorKind::InvalidToken { found, .. } if found == "-4"));
+		let (line, column, _) = parse_error(&BOSS.replace("$hitbox", "$shape"));
+		assert_eq!((line, column), (2, 2));
+
+		let level = parse_data(
+			"(event_list: [(variant: SpawnBoss((x: 0.0, y: 0.0), (name: \"warden\", \
+			hitbox: Some(Aabb((w: 0.0, h: 10.0))), phases: [(hp: 10.0, timeout: None, \
+			movement: hover, pattern: aimed, fire_interval: (secs: 1, nanos: 0))])))])",
+		);
+		assert!(matches!(
+			level.unwrap_err().kind,
+			ParseErrorKind::InvalidToken { found, .. } if found == "box 0 10"
+		));
+	}
+
+	#[test]
+	fn cancellable_conditions_are_warned() {
+		let level = parse(
+			"@spawn-enemy basic 1 400 -25 -\n\
+			@spawn-enemy basic 1 400 -25 score:100\n\
+			@spawn-enemy basic 1 400 -25 hit\n\
+			@spawn-enemy basic 1 400 -25 enemies-below:1",
+		)
+		.unwrap();
+		let report = level.validate();
+		assert!(report.errors.is_empty());
+		let warnings: Vec<String> = report.warnings.iter().map(|w| w.to_string()).collect();
+		assert_eq!(
+			warnings,
+			[
+				"event 1 waits for 'score:100', it is cancelled if the level is cleared before",
+				"event 2 waits for 'hit', it is cancelled if the level is cleared before",
+			]
+		);
+
+		let (line, column, _) = parse_error("@spawn-enemy basic 1 400 -25 enemies-below:0");
+		assert_eq!((line, column), (1, 30));
+	}
+}
@@ -0,0 +1,18 @@
+pub mod boss;
+pub mod bot;
+pub mod campaign;
+pub mod clock;
+pub mod coords;
+pub mod draw;
+pub mod enemy;
+pub mod game;
+pub mod gameloop;
+pub mod gameplay;
+pub mod grid;
+pub mod headless;
+pub mod level;
+pub mod path;
+pub mod pattern;
+pub mod projectiles;
+pub mod replay;
+pub mod sound;
@@ -1,12 +1,186 @@
-mod coords;
-mod draw;
-mod game;
-mod gameloop;
-mod gameplay;
-mod sound;
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+	process::ExitCode,
+	rc::Rc,
+};
 
-use crate::gameloop::game_run;
+use holy_bullet_hell::{
+	enemy::{EnemyTypes, ENEMY_DIR},
+	gameloop::game_run,
+	gameplay::LevelEnd,
+	headless::{run_autopilot, run_level, run_replay, MAX_TICKS},
+	level::{format_hbh, is_level_file, Level, LevelError},
+	replay::Replay,
+};
 
-fn main() {
-	game_run().unwrap();
+fn main() -> ExitCode {
+	let args: Vec<String> = env::args().skip(1).collect();
+	match args.first().map(String::as_str) {
+		Some("--fmt") => format_level(args.get(1)),
+		Some("--headless") => headless(args.get(1), args.get(2)),
+		Some("--autopilot") => autopilot(args.get(1)),
+		Some("--replay") => {
+			let Some(path) = args.get(1) else {
+				eprintln!("Usage: holy_bullet_hell --replay <replay file>");
+				return ExitCode::FAILURE;
+			};
+			match Replay::load(Path::new(path)) {
+				Ok(replay) => {
+					game_run(Some(replay)).unwrap();
+					ExitCode::SUCCESS
+				},
+				Err(err) => {
+					eprintln!("{path}: {err}");
+					ExitCode::FAILURE
+				},
+			}
+		},
+		_ => {
+			game_run(None).unwrap();
+			ExitCode::SUCCESS
+		},
+	}
+}
+
+/// Plays the level without window with the inputs of the replay, or without inputs, and prints
+/// how it ended
+fn headless(level_path: Option<&String>, replay_path: Option<&String>) -> ExitCode {
+	let Some(level_path) = level_path else {
+		eprintln!("Usage: holy_bullet_hell --headless <level file> [replay file]");
+		return ExitCode::FAILURE;
+	};
+	let enemy_types = match EnemyTypes::load(Path::new(ENEMY_DIR)) {
+		Ok(enemy_types) => Rc::new(enemy_types),
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	let level = match load_level(Path::new(level_path), &enemy_types) {
+		Ok(level) => level,
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	let outcome = match replay_path.map(|path| (path, Replay::load(Path::new(path)))) {
+		Some((path, replay)) => {
+			match replay.and_then(|replay| run_replay(&level, enemy_types, replay)) {
+				Ok(outcome) => outcome,
+				Err(err) => {
+					eprintln!("{path}: {err}");
+					return ExitCode::FAILURE;
+				},
+			}
+		},
+		None => run_level(&level, enemy_types, MAX_TICKS, |_| Some(Default::default())),
+	};
+	println!("{}: {outcome}", level.file);
+	if outcome.end == Some(LevelEnd::Cleared) {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::FAILURE
+	}
+}
+
+/// Plays the levels of the directory, or the level file, with the autopilot and prints how each
+/// run ended
+fn autopilot(path: Option<&String>) -> ExitCode {
+	let Some(path) = path.map(Path::new) else {
+		eprintln!("Usage: holy_bullet_hell --autopilot <level file or directory>");
+		return ExitCode::FAILURE;
+	};
+	let enemy_types = match EnemyTypes::load(Path::new(ENEMY_DIR)) {
+		Ok(enemy_types) => Rc::new(enemy_types),
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	let paths: Vec<PathBuf> = if path.is_dir() {
+		let mut paths: Vec<PathBuf> = match fs::read_dir(path) {
+			Ok(entries) => entries
+				.filter_map(|entry| Some(entry.ok()?.path()))
+				.filter(|path| is_level_file(path))
+				.collect(),
+			Err(err) => {
+				eprintln!("{}: {err}", path.display());
+				return ExitCode::FAILURE;
+			},
+		};
+		paths.sort();
+		paths
+	} else {
+		vec![path.to_path_buf()]
+	};
+
+	let mut cleared = 0;
+	for path in &paths {
+		match load_level(path, &enemy_types) {
+			Ok(level) => {
+				let outcome = run_autopilot(&level, enemy_types.clone(), MAX_TICKS);
+				println!("{}: {outcome}", level.file);
+				if outcome.end == Some(LevelEnd::Cleared) {
+					cleared += 1;
+				}
+			},
+			Err(err) => println!("{}: invalid level\n{err}", path.display()),
+		}
+	}
+	println!(
+		"{cleared}/{} level(s) cleared by the autopilot",
+		paths.len()
+	);
+	if cleared == paths.len() {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::FAILURE
+	}
+}
+
+/// Loads the level and checks its events, like the game does
+fn load_level(path: &Path, enemy_types: &EnemyTypes) -> Result<Level, LevelError> {
+	let level = Level::load(path, 0, enemy_types).map_err(LevelError::Parse)?;
+	let report = level.validate();
+	if !report.errors.is_empty() {
+		return Err(LevelError::Events(report.errors));
+	}
+	Ok(level)
+}
+
+/// Prints the level file with a normalized layout, levels in RON or JSON are converted to `.hbh`
+fn format_level(path: Option<&String>) -> ExitCode {
+	let Some(path) = path else {
+		eprintln!("Usage: holy_bullet_hell --fmt <level file>");
+		return ExitCode::FAILURE;
+	};
+	let enemy_types = match EnemyTypes::load(Path::new(ENEMY_DIR)) {
+		Ok(enemy_types) => enemy_types,
+		Err(err) => {
+			eprintln!("{err}");
+			return ExitCode::FAILURE;
+		},
+	};
+	// Invalid files aren't formatted, the layout of their lines may be wrong
+	match Level::load(Path::new(path), 0, &enemy_types) {
+		Ok(level) if path.ends_with(".hbh.ron") || path.ends_with(".hbh.json") => {
+			print!("{}", level.to_hbh());
+			ExitCode::SUCCESS
+		},
+		Ok(_) => match fs::read_to_string(path) {
+			Ok(source) => {
+				print!("{}", format_hbh(&source));
+				ExitCode::SUCCESS
+			},
+			Err(err) => {
+				eprintln!("{path}: {err}");
+				ExitCode::FAILURE
+			},
+		},
+		Err(err) => {
+			eprintln!("{err}");
+			ExitCode::FAILURE
+		},
+	}
 }
@@ -0,0 +1,184 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Vector2, Zero};
+use serde::{Deserialize, Serialize};
+use std::{rc::Rc, time::Duration};
+
+/// Number of straight lines used to draw a curve
+const CURVE_SAMPLES: usize = 16;
+
+/// Part of an enemy path, points are relative to the spawn position of the enemy and speeds are
+/// in pixels per second
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathSegment {
+	/// Straight line to the point
+	Line { to: Point2<f32>, speed: f32 },
+	/// Bézier curve, the last point is the end of the curve and the others are control points
+	Bezier { points: Vec<Point2<f32>>, speed: f32 },
+	/// Catmull-Rom spline going through the points
+	Spline { points: Vec<Point2<f32>>, speed: f32 },
+	/// Stays in place
+	Hold(Duration),
+}
+
+impl PathSegment {
+	pub fn speed(&self) -> Option<f32> {
+		match self {
+			PathSegment::Line { speed, .. }
+			| PathSegment::Bezier { speed, .. }
+			| PathSegment::Spline { speed, .. } => Some(*speed),
+			PathSegment::Hold(_) => None,
+		}
+	}
+
+	pub fn points(&self) -> &[Point2<f32>] {
+		match self {
+			PathSegment::Line { to, .. } => std::slice::from_ref(to),
+			PathSegment::Bezier { points, .. } | PathSegment::Spline { points, .. } => points,
+			PathSegment::Hold(_) => &[],
+		}
+	}
+
+	/// Points to go through in world coordinates, the segment starts at `start`
+	fn polyline(&self, origin: Point2<f32>, start: Point2<f32>) -> Vec<Point2<f32>> {
+		let mut points = vec![start];
+		points.extend(self.points().iter().map(|point| origin + point.to_vec()));
+		match self {
+			PathSegment::Line { .. } | PathSegment::Hold(_) => points.split_off(1),
+			PathSegment::Bezier { .. } => (1..=CURVE_SAMPLES)
+				.map(|i| bezier(&points, i as f32 / CURVE_SAMPLES as f32))
+				.collect(),
+			PathSegment::Spline { .. } => {
+				let last = points.len() - 1;
+				let mut line = vec![];
+				for i in 0..last {
+					// The ends of the spline are repeated to have a point before and after
+					let p0 = points[i.saturating_sub(1)];
+					let p3 = points[(i + 2).min(last)];
+					for j in 1..=CURVE_SAMPLES {
+						let t = j as f32 / CURVE_SAMPLES as f32;
+						line.push(catmull_rom(p0, points[i], points[i + 1], p3, t));
+					}
+				}
+				line
+			},
+		}
+	}
+}
+
+/// De Casteljau's algorithm
+fn bezier(points: &[Point2<f32>], t: f32) -> Point2<f32> {
+	let mut points = points.to_vec();
+	while points.len() > 1 {
+		points = points
+			.windows(2)
+			.map(|pair| pair[0] + (pair[1] - pair[0]) * t)
+			.collect();
+	}
+	points[0]
+}
+
+/// Point of the spline between `p1` and `p2`
+fn catmull_rom(
+	p0: Point2<f32>,
+	p1: Point2<f32>,
+	p2: Point2<f32>,
+	p3: Point2<f32>,
+	t: f32,
+) -> Point2<f32> {
+	let (p0, p1, p2, p3) = (p0.to_vec(), p1.to_vec(), p2.to_vec(), p3.to_vec());
+	let point = 0.5
+		* (2. * p1
+			+ (p2 - p0) * t
+			+ (2. * p0 - 5. * p1 + 4. * p2 - p3) * t * t
+			+ (3. * p1 - p0 - 3. * p2 + p3) * t * t * t);
+	Point2::from_vec(point)
+}
+
+/// Path declared with `@path` in a level file, shared by the enemies following it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnemyPath {
+	pub name: String,
+	pub segments: Vec<PathSegment>,
+}
+
+/// Position of an enemy on its path
+#[derive(Clone, Debug)]
+pub struct PathFollower {
+	path: Rc<EnemyPath>,
+	/// Spawn position of the enemy
+	origin: Point2<f32>,
+	segment: usize,
+	/// Points of the current segment left to reach, the next one is last
+	points: Vec<Point2<f32>>,
+	/// Time spent on the current hold
+	held: f32,
+	/// At the end of the path, the enemy keeps its last velocity
+	pub vel: Vector2<f32>,
+}
+
+impl PathFollower {
+	pub fn new(path: Rc<EnemyPath>, origin: Point2<f32>) -> PathFollower {
+		let mut follower = PathFollower {
+			path,
+			origin,
+			segment: 0,
+			points: vec![],
+			held: 0.,
+			vel: Vector2::zero(),
+		};
+		follower.start_segment(origin);
+		follower
+	}
+
+	fn start_segment(&mut self, pos: Point2<f32>) {
+		self.held = 0.;
+		self.points = match self.path.segments.get(self.segment) {
+			Some(segment) => segment.polyline(self.origin, pos),
+			None => vec![],
+		};
+		self.points.reverse();
+	}
+
+	/// Every segment of the path is done, the enemy goes on with its last velocity
+	pub fn finished(&self) -> bool {
+		self.segment >= self.path.segments.len()
+	}
+
+	/// Moves `pos` along the path for `dt` seconds
+	pub fn advance(&mut self, pos: &mut Point2<f32>, mut dt: f32) {
+		while dt > 0. {
+			let Some(segment) = self.path.segments.get(self.segment) else {
+				*pos += self.vel * dt;
+				return;
+			};
+			match segment {
+				PathSegment::Hold(hold) => {
+					self.vel = Vector2::zero();
+					let left = hold.as_secs_f32() - self.held;
+					if dt < left {
+						self.held += dt;
+						return;
+					}
+					dt -= left;
+				},
+				segment => {
+					let speed = segment.speed().unwrap_or_default();
+					while let Some(&target) = self.points.last() {
+						let to_target = target - *pos;
+						let dist = to_target.magnitude();
+						if speed * dt < dist {
+							self.vel = to_target / dist * speed;
+							*pos += self.vel * dt;
+							return;
+						}
+						*pos = target;
+						dt -= dist / speed;
+						self.points.pop();
+					}
+				},
+			}
+			self.segment += 1;
+			self.start_segment(*pos);
+		}
+	}
+}
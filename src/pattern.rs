@@ -0,0 +1,463 @@
+use cgmath::{Point2, Vector2};
+use std::{path::Path, rc::Rc, time::Duration};
+
+use crate::{
+	gameplay::ProjType,
+	level::{parse_time, LevelParseError, LineTokens, ParseErrorKind, MAX_REPEAT},
+};
+
+/// Actions run in a single update at most, the others wait for the next update
+const MAX_ACTIONS: usize = 1000;
+
+/// Angle of a bullet in degrees, 0 goes down and 90 goes right
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+	/// Offset from the direction of the player
+	Aim(f32),
+	Absolute(f32),
+	/// Offset from the direction of the previous bullet of the pattern
+	Sequence(f32),
+	/// Offset from the direction of the bullet running the pattern, down for enemies
+	Relative(f32),
+}
+
+/// Instruction of a bullet pattern
+#[derive(Clone, Debug)]
+pub enum Action {
+	/// Shoots a bullet, which runs its own actions if it has any
+	Fire {
+		speed: f32,
+		dir: Direction,
+		actions: Option<Rc<[Action]>>,
+	},
+	Wait(Duration),
+	/// Runs the actions `count` times, forever without count
+	Repeat {
+		count: Option<u32>,
+		actions: Rc<[Action]>,
+	},
+	/// Changes the speed of the bullet running the pattern over some time
+	ChangeSpeed {
+		speed: f32,
+		over: Duration,
+	},
+	/// Turns the bullet running the pattern over some time
+	ChangeDirection {
+		dir: Direction,
+		over: Duration,
+	},
+	/// Removes the bullet running the pattern
+	Vanish,
+}
+
+/// Pattern defined with `@pattern` in the enemy files, or built from a `$shot` line
+#[derive(Clone, Debug)]
+pub struct BulletPattern {
+	pub name: String,
+	pub actions: Rc<[Action]>,
+	/// Look of the bullets, children included
+	pub variant: ProjType,
+}
+
+/// Enemy or bullet running a pattern
+#[derive(Clone, Copy, Debug)]
+pub struct Emitter {
+	pub pos: Point2<f32>,
+	/// Direction in degrees
+	pub dir: f32,
+	/// Speed in pixels per second
+	pub speed: f32,
+	pub vanished: bool,
+}
+
+/// Bullet fired by a pattern
+#[derive(Clone, Debug)]
+pub struct Shot {
+	pub dir: f32,
+	pub speed: f32,
+	pub runner: Option<PatternRunner>,
+}
+
+/// Pattern run by a bullet, with the direction and speed it controls
+#[derive(Clone, Debug)]
+pub struct BulletScript {
+	pub runner: PatternRunner,
+	pub emitter: Emitter,
+}
+
+/// Vector of length 1 for an angle in degrees
+pub fn direction_vector(angle: f32) -> Vector2<f32> {
+	let angle = angle.to_radians();
+	Vector2::new(angle.sin(), angle.cos())
+}
+
+/// Angle in degrees of a vector
+pub fn vector_angle(vec: Vector2<f32>) -> f32 {
+	vec.x.atan2(vec.y).to_degrees()
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+	actions: Rc<[Action]>,
+	next: usize,
+	/// Iterations left after the current one, `None` repeats forever
+	repeats_left: Option<u32>,
+}
+
+/// Progress of an emitter in its pattern
+#[derive(Clone, Debug)]
+pub struct PatternRunner {
+	actions: Rc<[Action]>,
+	frames: Vec<Frame>,
+	/// Time before the next action, negative when the last wait ended during the update
+	wait: f32,
+	/// Direction of the previous bullet, for `seq`
+	last_dir: f32,
+	/// Change per second and time left of the speed and of the direction of the emitter
+	speed_change: Option<(f32, f32)>,
+	dir_change: Option<(f32, f32)>,
+	/// Top level patterns start again when they end, the ones of bullets only run once
+	looping: bool,
+}
+
+impl PatternRunner {
+	pub fn new(actions: Rc<[Action]>, looping: bool) -> PatternRunner {
+		PatternRunner {
+			frames: vec![Frame { actions: actions.clone(), next: 0, repeats_left: Some(0) }],
+			actions,
+			wait: 0.,
+			last_dir: 0.,
+			speed_change: None,
+			dir_change: None,
+			looping,
+		}
+	}
+
+	fn angle(&self, dir: Direction, emitter: &Emitter, player_pos: Point2<f32>) -> f32 {
+		match dir {
+			Direction::Aim(offset) => vector_angle(player_pos - emitter.pos) + offset,
+			Direction::Absolute(angle) => angle,
+			Direction::Sequence(offset) => self.last_dir + offset,
+			Direction::Relative(offset) => emitter.dir + offset,
+		}
+	}
+
+	/// Runs the pattern for `dt` seconds and returns the bullets fired
+	pub fn update(&mut self, emitter: &mut Emitter, player_pos: Point2<f32>, dt: f32) -> Vec<Shot> {
+		if let Some((rate, left)) = &mut self.speed_change {
+			emitter.speed += *rate * dt.min(*left);
+			*left -= dt;
+			if *left <= 0. {
+				self.speed_change = None;
+			}
+		}
+		if let Some((rate, left)) = &mut self.dir_change {
+			emitter.dir += *rate * dt.min(*left);
+			*left -= dt;
+			if *left <= 0. {
+				self.dir_change = None;
+			}
+		}
+
+		let mut shots = vec![];
+		self.wait -= dt;
+		let mut budget = MAX_ACTIONS;
+		while self.wait <= 0. && budget > 0 {
+			let Some(frame) = self.frames.last_mut() else {
+				if !self.looping {
+					break;
+				}
+				self.frames.push(Frame {
+					actions: self.actions.clone(),
+					next: 0,
+					repeats_left: Some(0),
+				});
+				continue;
+			};
+			let Some(action) = frame.actions.get(frame.next).cloned() else {
+				match &mut frame.repeats_left {
+					Some(0) => {
+						self.frames.pop();
+					},
+					Some(n) => {
+						*n -= 1;
+						frame.next = 0;
+					},
+					None => frame.next = 0,
+				}
+				budget -= 1;
+				continue;
+			};
+			frame.next += 1;
+			budget -= 1;
+			match action {
+				Action::Fire { speed, dir, actions } => {
+					let dir = self.angle(dir, emitter, player_pos);
+					self.last_dir = dir;
+					let runner = actions.map(|actions| PatternRunner::new(actions, false));
+					shots.push(Shot { dir, speed, runner });
+				},
+				Action::Wait(t) => self.wait += t.as_secs_f32(),
+				Action::Repeat { count, actions } => {
+					if count != Some(0) {
+						let repeats_left = count.map(|count| count - 1);
+						self.frames.push(Frame { actions, next: 0, repeats_left });
+					}
+				},
+				Action::ChangeSpeed { speed, over } => {
+					if over.is_zero() {
+						emitter.speed = speed;
+					} else {
+						let over = over.as_secs_f32();
+						self.speed_change = Some(((speed - emitter.speed) / over, over));
+					}
+				},
+				Action::ChangeDirection { dir, over } => {
+					let target = self.angle(dir, emitter, player_pos);
+					// Turns the shortest way
+					let delta = (target - emitter.dir + 180.).rem_euclid(360.) - 180.;
+					if over.is_zero() {
+						emitter.dir += delta;
+					} else {
+						let over = over.as_secs_f32();
+						self.dir_change = Some((delta / over, over));
+					}
+				},
+				Action::Vanish => {
+					emitter.vanished = true;
+					self.frames.clear();
+					self.looping = false;
+				},
+			}
+		}
+		self.wait = self.wait.max(0.);
+		shots
+	}
+}
+
+/// Whether the actions wait at some point, a pattern repeated forever without wait never ends
+pub fn waits(actions: &[Action]) -> bool {
+	actions.iter().any(|action| match action {
+		Action::Wait(t) => !t.is_zero(),
+		Action::Repeat { count, actions } => *count != Some(0) && waits(actions),
+		_ => false,
+	})
+}
+
+/// Reads the actions of a pattern block until its closing `}`
+pub fn parse_actions<'a>(
+	file: &Path,
+	lines: &mut impl Iterator<Item = (usize, usize, &'a str)>,
+	open: (usize, usize),
+) -> Result<Rc<[Action]>, LevelParseError> {
+	let mut actions = vec![];
+	while let Some((number, column, content)) = lines.next() {
+		if content == "}" {
+			return Ok(actions.into());
+		}
+		let Some(data) = content.strip_prefix('@') else {
+			continue;
+		};
+		let mut tokens = LineTokens::new(file, number, data, column);
+		const EXPECTED: &str = "pattern action (fire, wait, repeat, speed, turn, vanish)";
+		let (col, key) = tokens.next(EXPECTED)?;
+		let action = match key {
+			"fire" => {
+				let speed = tokens.parse_finite("bullet speed in pixels per second")?;
+				let dir = parse_direction(&mut tokens)?;
+				let actions = match tokens.next("'{' opening the actions of the bullet") {
+					Ok((col, "{")) => Some(parse_actions(file, lines, (number, col))?),
+					Ok((col, token)) => {
+						return Err(tokens.error(col, ParseErrorKind::TrailingToken(token.into())))
+					},
+					Err(_) => None,
+				};
+				Action::Fire { speed, dir, actions }
+			},
+			"wait" => Action::Wait(parse_time(&mut tokens)?),
+			"repeat" => {
+				const COUNT_EXPECTED: &str = "repeat count (1 to 1000 or forever)";
+				let (count_col, count) = tokens.next(COUNT_EXPECTED)?;
+				let count = match count {
+					"forever" => None,
+					count => match count.parse::<u32>() {
+						Ok(count) if (1..=MAX_REPEAT).contains(&count) => Some(count),
+						_ => {
+							return Err(tokens.error(
+								count_col,
+								ParseErrorKind::InvalidToken {
+									expected: COUNT_EXPECTED,
+									found: count.into(),
+								},
+							))
+						},
+					},
+				};
+				let (open_col, open) = tokens.next("'{' opening the repeated actions")?;
+				if open != "{" {
+					return Err(tokens.error(
+						open_col,
+						ParseErrorKind::InvalidToken {
+							expected: "'{' opening the repeated actions",
+							found: open.into(),
+						},
+					));
+				}
+				let actions = parse_actions(file, lines, (number, open_col))?;
+				if count.is_none() && !waits(&actions) {
+					return Err(tokens.error(
+						count_col,
+						ParseErrorKind::InvalidToken {
+							expected: "wait in the actions repeated forever",
+							found: "forever".into(),
+						},
+					));
+				}
+				Action::Repeat { count, actions }
+			},
+			"speed" => {
+				let speed = tokens.parse_finite("bullet speed in pixels per second")?;
+				Action::ChangeSpeed { speed, over: parse_time(&mut tokens)? }
+			},
+			"turn" => {
+				let dir = parse_direction(&mut tokens)?;
+				Action::ChangeDirection { dir, over: parse_time(&mut tokens)? }
+			},
+			"vanish" => Action::Vanish,
+			other => {
+				return Err(tokens.error(
+					col,
+					ParseErrorKind::InvalidToken { expected: EXPECTED, found: other.into() },
+				))
+			},
+		};
+		tokens.finish()?;
+		actions.push(action);
+	}
+	Err(LevelParseError {
+		file: file.to_path_buf(),
+		line: open.0,
+		column: open.1,
+		kind: ParseErrorKind::UnclosedBlock,
+	})
+}
+
+/// Parses `<aim|abs|seq|rel> <degrees>`
+fn parse_direction(tokens: &mut LineTokens) -> Result<Direction, LevelParseError> {
+	const EXPECTED: &str = "direction (aim, abs, seq, rel)";
+	let (col, kind) = tokens.next(EXPECTED)?;
+	let kind = match kind {
+		"aim" => Direction::Aim,
+		"abs" => Direction::Absolute,
+		"seq" => Direction::Sequence,
+		"rel" => Direction::Relative,
+		other => {
+			return Err(tokens.error(
+				col,
+				ParseErrorKind::InvalidToken { expected: EXPECTED, found: other.into() },
+			))
+		},
+	};
+	Ok(kind(tokens.parse_finite("angle in degrees")?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Parses the actions of a pattern opened on line 1
+	fn parse(content: &str) -> Result<Rc<[Action]>, LevelParseError> {
+		let mut lines = content.lines().enumerate().map(|(i, line)| {
+			let trimmed = line.trim_start();
+			(i + 2, line.len() - trimmed.len() + 1, trimmed.trim_end())
+		});
+		parse_actions(Path::new("test.hbe"), &mut lines, (1, 1))
+	}
+
+	fn emitter() -> Emitter {
+		Emitter { pos: Point2::new(0., 0.), dir: 0., speed: 0., vanished: false }
+	}
+
+	#[test]
+	fn waits_cases() {
+		let fire = || Action::Fire { speed: 100., dir: Direction::Absolute(0.), actions: None };
+		let wait = |secs| Action::Wait(Duration::from_secs_f32(secs));
+		let repeat = |count, actions: Vec<Action>| Action::Repeat { count, actions: actions.into() };
+		assert!(!waits(&[]));
+		assert!(!waits(&[fire()]));
+		assert!(!waits(&[fire(), wait(0.)]));
+		assert!(waits(&[fire(), wait(0.5)]));
+		assert!(waits(&[repeat(Some(3), vec![fire(), wait(0.5)])]));
+		assert!(waits(&[repeat(None, vec![wait(0.5)])]));
+		assert!(!waits(&[repeat(Some(0), vec![wait(0.5)])]));
+		assert!(!waits(&[repeat(Some(3), vec![fire()])]));
+	}
+
+	#[test]
+	fn forever_needs_a_wait() {
+		let err = parse("\t@repeat forever {\n\t\t@fire 100 abs 0\n\t}\n}").unwrap_err();
+		assert_eq!((err.line, err.column), (2, 10));
+		assert!(parse("\t@repeat forever {\n\t\t@fire 100 abs 0\n\t\t@wait 0.1\n\t}\n}").is_ok());
+		let err = parse("\t@repeat 3 {\n\t\t@fire 100 abs 0\n").unwrap_err();
+		assert_eq!((err.line, err.column), (2, 12));
+		assert!(matches!(err.kind, ParseErrorKind::UnclosedBlock));
+	}
+
+	#[test]
+	fn actions_per_update_are_bounded() {
+		let actions =
+			parse("\t@repeat 5 {\n\t\t@repeat 500 {\n\t\t\t@fire 100 abs 0\n\t\t}\n\t}\n}").unwrap();
+		let mut runner = PatternRunner::new(actions, false);
+		let mut emitter = emitter();
+		let fired: Vec<usize> = (0..7)
+			.map(|_| runner.update(&mut emitter, Point2::new(0., 0.), 0.).len())
+			.collect();
+		// The repeats and the end of each iteration are actions too, a fire and the end of its
+		// iteration take two
+		assert!(fired.iter().all(|&n| n <= MAX_ACTIONS / 2), "{fired:?}");
+		assert!(fired[0] >= MAX_ACTIONS / 2 - 1, "{fired:?}");
+		assert_eq!(fired.iter().sum::<usize>(), 2500);
+		assert_eq!(fired[6], 0);
+	}
+
+	#[test]
+	fn invalid_values_are_rejected() {
+		for (line, column, found) in [
+			("\t@fire NaN abs 0", 8, "NaN"),
+			("\t@fire 100 abs inf", 16, "inf"),
+			("\t@fire 100 seq -inf", 16, "-inf"),
+			("\t@speed inf 1", 9, "inf"),
+			("\t@turn rel NaN 1", 12, "NaN"),
+			("\t@repeat 0 {", 10, "0"),
+			("\t@repeat 1001 {", 10, "1001"),
+		] {
+			let err = parse(&format!("{line}\n\t\t@wait 1\n\t}}\n}}")).unwrap_err();
+			assert_eq!((err.line, err.column), (2, column), "{line}");
+			match err.kind {
+				ParseErrorKind::InvalidToken { found: token, .. } => assert_eq!(token, found),
+				kind => panic!("{line}: {kind:?}"),
+			}
+		}
+	}
+
+	#[test]
+	fn waits_between_updates() {
+		let actions = parse("\t@fire 100 abs 0\n\t@wait 0.5\n}").unwrap();
+		let mut runner = PatternRunner::new(actions, true);
+		let mut emitter = emitter();
+		let mut update = |dt| runner.update(&mut emitter, Point2::new(0., 0.), dt).len();
+		assert_eq!(update(0.), 1);
+		assert_eq!(update(0.25), 0);
+		assert_eq!(update(0.25), 1);
+		assert_eq!(update(1.), 2);
+	}
+
+	#[test]
+	fn aims_at_the_player() {
+		let actions = parse("\t@fire 100 aim 10\n\t@fire 100 seq 5\n\t@wait 1\n}").unwrap();
+		let mut runner = PatternRunner::new(actions, true);
+		let shots = runner.update(&mut emitter(), Point2::new(50., 0.), 0.);
+		let dirs: Vec<f32> = shots.iter().map(|shot| shot.dir).collect();
+		assert_eq!(dirs, [100., 105.]);
+	}
+}
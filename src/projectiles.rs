@@ -0,0 +1,268 @@
+use cgmath::{Point2, Vector2};
+
+use crate::{
+	clock::TICKS_PER_SEC,
+	coords::RectF,
+	gameplay::{ProjType, DT_60},
+	pattern::{direction_vector, BulletScript, Emitter, Shot},
+};
+
+/// Ticks a projectile lives at most, bullets stopped by their pattern would never leave otherwise
+pub const MAX_LIFETIME: u32 = 30 * TICKS_PER_SEC;
+
+/// Projectile to add to the world
+#[derive(Clone, Debug)]
+pub struct Projectile {
+	pub pos: Point2<f32>,
+	/// Movement per tick
+	pub vel: Vector2<f32>,
+	pub variant: ProjType,
+	/// Pattern run by the bullet, for the bullets fired by patterns with sub-bullets
+	script: Option<Box<BulletScript>>,
+}
+
+impl Projectile {
+	pub fn new(pos: Point2<f32>, vel: Vector2<f32>, variant: ProjType) -> Self {
+		Projectile { pos, vel, variant, script: None }
+	}
+
+	/// Bullet fired by a pattern, speeds of patterns are per second and velocities are per frame
+	/// at 60 FPS
+	pub fn fired(pos: Point2<f32>, shot: Shot, variant: ProjType) -> Self {
+		let script = shot.runner.map(|runner| {
+			let emitter = Emitter { pos, dir: shot.dir, speed: shot.speed, vanished: false };
+			Box::new(BulletScript { runner, emitter })
+		});
+		let vel = direction_vector(shot.dir) * shot.speed * DT_60;
+		Projectile { pos, vel, variant, script }
+	}
+}
+
+/// Projectiles of the world, with an array for each field
+///
+/// A projectile is the index of its slot in the arrays. Removing a projectile frees its slot for
+/// the next one, so the arrays only grow to the most projectiles alive at once, and the passes
+/// over the projectiles read one array after the other.
+#[derive(Clone, Debug, Default)]
+pub struct Projectiles {
+	pub pos: Vec<Point2<f32>>,
+	/// Positions at the previous tick, for drawing between ticks
+	pub prev_pos: Vec<Point2<f32>>,
+	/// Movement per tick
+	pub vel: Vec<Vector2<f32>>,
+	pub variant: Vec<ProjType>,
+	/// Ticks left before the projectile is removed
+	pub lifetime: Vec<u32>,
+	scripts: Vec<Option<Box<BulletScript>>>,
+	alive: Vec<bool>,
+	/// Slots of the removed projectiles
+	free: Vec<usize>,
+}
+
+impl Projectiles {
+	/// Number of projectiles alive
+	pub fn len(&self) -> usize {
+		self.alive.len() - self.free.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn is_alive(&self, slot: usize) -> bool {
+		self.alive[slot]
+	}
+
+	/// Slots of the projectiles alive, in order
+	pub fn slots(&self) -> impl Iterator<Item = usize> + '_ {
+		self
+			.alive
+			.iter()
+			.enumerate()
+			.filter(|(_, alive)| **alive)
+			.map(|(slot, _)| slot)
+	}
+
+	/// Adds a projectile in a free slot if there is one, and returns its slot
+	pub fn push(&mut self, proj: Projectile) -> usize {
+		let Projectile { pos, vel, variant, script } = proj;
+		match self.free.pop() {
+			Some(slot) => {
+				self.pos[slot] = pos;
+				self.prev_pos[slot] = pos;
+				self.vel[slot] = vel;
+				self.variant[slot] = variant;
+				self.lifetime[slot] = MAX_LIFETIME;
+				self.scripts[slot] = script;
+				self.alive[slot] = true;
+				slot
+			},
+			None => {
+				self.pos.push(pos);
+				self.prev_pos.push(pos);
+				self.vel.push(vel);
+				self.variant.push(variant);
+				self.lifetime.push(MAX_LIFETIME);
+				self.scripts.push(script);
+				self.alive.push(true);
+				self.alive.len() - 1
+			},
+		}
+	}
+
+	/// Frees the slot, the projectile isn't updated nor drawn anymore
+	pub fn remove(&mut self, slot: usize) {
+		if self.alive[slot] {
+			self.alive[slot] = false;
+			self.scripts[slot] = None;
+			self.free.push(slot);
+		}
+	}
+
+	pub fn store_positions(&mut self) {
+		self.prev_pos.copy_from_slice(&self.pos);
+	}
+
+	/// Runs the patterns of the bullets, moves the projectiles and removes the ones out of bounds
+	///
+	/// Returns the bullets fired by the patterns, to add once the collisions are resolved.
+	pub fn update(&mut self, bounds: RectF, player_pos: Point2<f32>) -> Vec<Projectile> {
+		let mut fired = vec![];
+		for slot in 0..self.scripts.len() {
+			let Some(script) = &mut self.scripts[slot] else {
+				continue;
+			};
+			script.emitter.pos = self.pos[slot];
+			let shots = script.runner.update(&mut script.emitter, player_pos, DT_60);
+			let variant = self.variant[slot];
+			fired.extend(
+				shots
+					.into_iter()
+					.map(|shot| Projectile::fired(self.pos[slot], shot, variant)),
+			);
+			if script.emitter.vanished {
+				self.remove(slot);
+			} else {
+				self.vel[slot] = direction_vector(script.emitter.dir) * script.emitter.speed * DT_60;
+			}
+		}
+
+		for slot in 0..self.alive.len() {
+			if !self.alive[slot] {
+				continue;
+			}
+			self.pos[slot] += self.vel[slot];
+			self.lifetime[slot] = self.lifetime[slot].saturating_sub(1);
+			if self.lifetime[slot] == 0 || !bounds.contains(self.pos[slot]) {
+				self.remove(slot);
+			}
+		}
+		fired
+	}
+}
+
+impl Extend<Projectile> for Projectiles {
+	fn extend<I: IntoIterator<Item = Projectile>>(&mut self, iter: I) {
+		for proj in iter {
+			self.push(proj);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use cgmath::InnerSpace;
+	use std::rc::Rc;
+
+	use super::*;
+	use crate::pattern::{Action, Direction, PatternRunner};
+
+	fn bounds() -> RectF {
+		RectF { top_left: (0., 0.).into(), dims: (100., 100.).into() }
+	}
+
+	fn basic(x: f32, y: f32, vx: f32) -> Projectile {
+		Projectile::new(Point2::new(x, y), Vector2::new(vx, 0.), ProjType::Basic)
+	}
+
+	#[test]
+	fn removed_slots_are_reused() {
+		let mut projectiles = Projectiles::default();
+		assert!(projectiles.is_empty());
+		let slots: Vec<usize> = (0..3)
+			.map(|i| projectiles.push(basic(i as f32, 0., 0.)))
+			.collect();
+		assert_eq!(slots, [0, 1, 2]);
+		projectiles.remove(1);
+		// Removing twice doesn't free the slot twice
+		projectiles.remove(1);
+		assert_eq!(projectiles.len(), 2);
+		assert!(!projectiles.is_alive(1));
+		assert_eq!(projectiles.slots().collect::<Vec<_>>(), [0, 2]);
+		assert_eq!(projectiles.push(basic(50., 50., 0.)), 1);
+		assert_eq!(projectiles.push(basic(60., 50., 0.)), 3);
+		assert_eq!(projectiles.len(), 4);
+		assert_eq!(projectiles.pos[1], Point2::new(50., 50.));
+		assert_eq!(projectiles.prev_pos[1], Point2::new(50., 50.));
+		assert_eq!(projectiles.lifetime[1], MAX_LIFETIME);
+	}
+
+	#[test]
+	fn update_moves_and_removes_out_of_bounds() {
+		let mut projectiles = Projectiles::default();
+		projectiles.extend([
+			basic(10., 10., 5.),
+			basic(98., 10., 5.),
+			basic(50., 50., -1.),
+		]);
+		projectiles.remove(2);
+		projectiles.update(bounds(), Point2::new(0., 0.));
+		assert_eq!(projectiles.pos[0], Point2::new(15., 10.));
+		assert_eq!(projectiles.slots().collect::<Vec<_>>(), [0]);
+		// The free slots don't move
+		assert_eq!(projectiles.pos[2], Point2::new(50., 50.));
+		projectiles.store_positions();
+		projectiles.update(bounds(), Point2::new(0., 0.));
+		assert_eq!(projectiles.prev_pos[0], Point2::new(15., 10.));
+		assert_eq!(projectiles.pos[0], Point2::new(20., 10.));
+	}
+
+	#[test]
+	fn projectiles_expire() {
+		let mut projectiles = Projectiles::default();
+		projectiles.push(basic(50., 50., 0.));
+		for _ in 1..MAX_LIFETIME {
+			projectiles.update(bounds(), Point2::new(0., 0.));
+		}
+		assert_eq!((projectiles.len(), projectiles.lifetime[0]), (1, 1));
+		projectiles.update(bounds(), Point2::new(0., 0.));
+		assert!(projectiles.is_empty());
+	}
+
+	#[test]
+	fn scripts_fire_and_vanish() {
+		let actions: Rc<[Action]> = vec![
+			Action::Fire { speed: 60., dir: Direction::Absolute(0.), actions: None },
+			Action::Vanish,
+		]
+		.into();
+		let shot = Shot {
+			dir: 90.,
+			speed: 60.,
+			runner: Some(PatternRunner::new(actions, false)),
+		};
+		let mut projectiles = Projectiles::default();
+		projectiles.push(Projectile::fired(
+			Point2::new(50., 50.),
+			shot,
+			ProjType::Aimed,
+		));
+		let fired = projectiles.update(bounds(), Point2::new(0., 0.));
+		assert!(projectiles.is_empty());
+		assert_eq!(fired.len(), 1);
+		assert_eq!(fired[0].pos, Point2::new(50., 50.));
+		assert!(matches!(fired[0].variant, ProjType::Aimed));
+		// Going down at 60 pixels per second, 1 pixel per tick
+		assert!((fired[0].vel - Vector2::new(0., 1.)).magnitude2() < 1e-6);
+	}
+}
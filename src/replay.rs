@@ -0,0 +1,320 @@
+use std::{fmt, fs, path::Path};
+
+use crate::{
+	clock::TICKS_PER_SEC,
+	game::Inputs,
+	level::{Level, MAX_TIME},
+};
+
+/// Directory where the replays of the runs are saved
+pub const REPLAY_DIR: &str = "./replays";
+/// Start of the replay files, with the version of the format
+const MAGIC: &[u8; 4] = b"HBR\x01";
+/// Most ticks in a replay, the longest time limit of a level
+const MAX_REPLAY_TICKS: u64 = MAX_TIME.as_secs() * TICKS_PER_SEC as u64;
+
+/// Inputs of every tick of a run, enough to simulate it again
+///
+/// The file starts with the header (game version, level, seed and number of ticks) and the inputs
+/// follow as runs of identical ticks, one byte for the keys and the length of the run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+	/// Version of the game that recorded the replay, other versions may play it differently
+	pub version: String,
+	/// File name of the level, the id is only used if no level has this name
+	pub level_file: String,
+	pub level_id: u32,
+	/// The simulation doesn't use randomness yet, the seed is always 0
+	pub seed: u64,
+	pub inputs: Vec<Inputs>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ReplayError {
+	Io(String),
+	/// The file isn't a replay or is cut short
+	Invalid(&'static str),
+	/// The replay was recorded on another level
+	WrongLevel {
+		replay: String,
+		level: String,
+	},
+}
+
+impl fmt::Display for ReplayError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ReplayError::Io(err) => write!(f, "{err}"),
+			ReplayError::Invalid(reason) => write!(f, "invalid replay: {reason}"),
+			ReplayError::WrongLevel { replay, level } => {
+				write!(f, "replay of the level '{replay}', not of '{level}'")
+			},
+		}
+	}
+}
+
+fn inputs_to_bits(inputs: &Inputs) -> u8 {
+	[
+		inputs.left,
+		inputs.right,
+		inputs.up,
+		inputs.down,
+		inputs.shoot,
+	]
+	.iter()
+	.enumerate()
+	.map(|(i, pressed)| (*pressed as u8) << i)
+	.sum()
+}
+
+fn bits_to_inputs(bits: u8) -> Inputs {
+	let pressed = |i: u8| bits & (1 << i) != 0;
+	Inputs {
+		left: pressed(0),
+		right: pressed(1),
+		up: pressed(2),
+		down: pressed(3),
+		shoot: pressed(4),
+		..Default::default()
+	}
+}
+
+/// LEB128, 7 bits per byte with the high bit set on all the bytes but the last
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+	while value >= 0x80 {
+		bytes.push(value as u8 | 0x80);
+		value >>= 7;
+	}
+	bytes.push(value as u8);
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+	fn take(&mut self, n: usize) -> Result<&[u8], ReplayError> {
+		if self.bytes.len() < n {
+			return Err(ReplayError::Invalid("unexpected end of file"));
+		}
+		let (taken, rest) = self.bytes.split_at(n);
+		self.bytes = rest;
+		Ok(taken)
+	}
+
+	fn byte(&mut self) -> Result<u8, ReplayError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn varint(&mut self) -> Result<u64, ReplayError> {
+		let mut value = 0;
+		for shift in (0..64).step_by(7) {
+			let byte = self.byte()?;
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		Err(ReplayError::Invalid("number too large"))
+	}
+
+	fn string(&mut self) -> Result<String, ReplayError> {
+		let len = self.varint()? as usize;
+		String::from_utf8(self.take(len)?.to_vec())
+			.map_err(|_| ReplayError::Invalid("text isn't UTF-8"))
+	}
+}
+
+impl Replay {
+	/// Empty replay of a run of the level
+	pub fn new(level: &Level) -> Replay {
+		Replay {
+			version: env!("CARGO_PKG_VERSION").into(),
+			level_file: level.file.clone(),
+			level_id: level.id,
+			seed: 0,
+			inputs: vec![],
+		}
+	}
+
+	/// Adds the inputs of a tick
+	pub fn record(&mut self, inputs: &Inputs) {
+		self.inputs.push(inputs.clone());
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		for text in [&self.version, &self.level_file] {
+			write_varint(&mut bytes, text.len() as u64);
+			bytes.extend(text.as_bytes());
+		}
+		write_varint(&mut bytes, self.level_id as u64);
+		write_varint(&mut bytes, self.seed);
+		write_varint(&mut bytes, self.inputs.len() as u64);
+		let bits: Vec<u8> = self.inputs.iter().map(inputs_to_bits).collect();
+		for run in bits.chunk_by(|a, b| a == b) {
+			bytes.push(run[0]);
+			write_varint(&mut bytes, run.len() as u64);
+		}
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Replay, ReplayError> {
+		let mut reader = Reader { bytes };
+		if reader.take(MAGIC.len()).ok() != Some(MAGIC) {
+			return Err(ReplayError::Invalid("not a replay file of this version"));
+		}
+		let version = reader.string()?;
+		let level_file = reader.string()?;
+		let level_id = reader.varint()? as u32;
+		let seed = reader.varint()?;
+		let ticks = reader.varint()?;
+		if ticks > MAX_REPLAY_TICKS {
+			return Err(ReplayError::Invalid("too many ticks"));
+		}
+		let ticks = ticks as usize;
+		let mut inputs = Vec::with_capacity(ticks);
+		while inputs.len() < ticks {
+			let tick_inputs = bits_to_inputs(reader.byte()?);
+			let run = reader.varint()? as usize;
+			if run == 0 || run > ticks - inputs.len() {
+				return Err(ReplayError::Invalid(
+					"inputs don't match the number of ticks",
+				));
+			}
+			inputs.extend(std::iter::repeat_n(tick_inputs, run));
+		}
+		Ok(Replay { version, level_file, level_id, seed, inputs })
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir).map_err(|err| ReplayError::Io(err.to_string()))?;
+		}
+		fs::write(path, self.to_bytes()).map_err(|err| ReplayError::Io(err.to_string()))
+	}
+
+	pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+		let bytes = fs::read(path).map_err(|err| ReplayError::Io(err.to_string()))?;
+		Replay::from_bytes(&bytes)
+	}
+
+	/// Level of the replay among the loaded ones, by file name then by id
+	pub fn find_level<'a>(&self, levels: &'a [Level]) -> Option<&'a Level> {
+		levels
+			.iter()
+			.find(|level| level.file == self.level_file)
+			.or_else(|| levels.get(self.level_id as usize))
+	}
+}
+
+/// Gives the inputs of a replay tick after tick
+#[derive(Clone, Debug)]
+pub struct ReplayPlayer {
+	pub replay: Replay,
+	tick: usize,
+}
+
+impl ReplayPlayer {
+	pub fn new(replay: Replay) -> ReplayPlayer {
+		ReplayPlayer { replay, tick: 0 }
+	}
+
+	/// Inputs of the next tick, `None` once the replay ended
+	pub fn next_inputs(&mut self) -> Option<Inputs> {
+		let inputs = self.replay.inputs.get(self.tick).cloned();
+		self.tick += 1;
+		inputs
+	}
+
+	pub fn is_over(&self) -> bool {
+		self.tick >= self.replay.inputs.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn replay(inputs: Vec<Inputs>) -> Replay {
+		Replay {
+			version: "0.1.0".into(),
+			level_file: "level1.hbh".into(),
+			level_id: 3,
+			seed: 0,
+			inputs,
+		}
+	}
+
+	/// Header of a replay up to the number of ticks, excluded
+	fn header() -> Vec<u8> {
+		let mut bytes = replay(vec![]).to_bytes();
+		bytes.pop();
+		bytes
+	}
+
+	#[test]
+	fn varint_round_trip() {
+		for value in [
+			0,
+			1,
+			0x7f,
+			0x80,
+			300,
+			0x3fff,
+			0x4000,
+			u32::MAX as u64,
+			u64::MAX,
+		] {
+			let mut bytes = vec![];
+			write_varint(&mut bytes, value);
+			assert_eq!(
+				bytes.len(),
+				(64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+			);
+			let mut reader = Reader { bytes: &bytes };
+			assert_eq!(reader.varint().unwrap(), value);
+			assert!(reader.bytes.is_empty());
+		}
+		let mut reader = Reader { bytes: &[0xff; 11] };
+		assert!(matches!(reader.varint(), Err(ReplayError::Invalid(_))));
+	}
+
+	#[test]
+	fn replay_round_trip() {
+		let shoot = Inputs { shoot: true, ..Default::default() };
+		let left = Inputs { left: true, shoot: true, ..Default::default() };
+		let mut inputs = vec![Inputs::default(); 3];
+		inputs.extend(vec![shoot; 200]);
+		inputs.extend([left, Inputs::default()]);
+		let replay = replay(inputs);
+		let bytes = replay.to_bytes();
+		// Header, then 4 runs of a byte and a varint, the run of 200 takes 2 bytes
+		assert_eq!(bytes.len(), header().len() + 2 + 9);
+		assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+	}
+
+	#[test]
+	fn corrupt_files() {
+		let invalid =
+			|bytes: &[u8]| matches!(Replay::from_bytes(bytes), Err(ReplayError::Invalid(_)));
+		assert!(invalid(b""));
+		assert!(invalid(b"HBR\x02"));
+		// Huge number of ticks, nothing is allocated for it
+		let mut bytes = header();
+		write_varint(&mut bytes, u64::MAX >> 1);
+		assert!(invalid(&bytes));
+		let mut bytes = header();
+		write_varint(&mut bytes, MAX_REPLAY_TICKS + 1);
+		assert!(invalid(&bytes));
+		// Runs longer than the ticks left
+		let mut bytes = header();
+		write_varint(&mut bytes, 10);
+		bytes.push(0);
+		write_varint(&mut bytes, u64::MAX);
+		assert!(invalid(&bytes));
+		// Inputs cut short
+		let bytes = replay(vec![Inputs::default(); 5]).to_bytes();
+		assert!(invalid(&bytes[..bytes.len() - 2]));
+	}
+}
@@ -3,7 +3,7 @@ use std::{collections::HashMap, path::Path, time::Duration};
 use kira::{
 	manager::{AudioManager, AudioManagerSettings},
 	sound::{
-		static_sound::{StaticSoundData, StaticSoundHandle},
+		static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings},
 		PlaybackState,
 	},
 	tween::Tween,
@@ -16,7 +16,7 @@ pub enum SoundBase {
 	MenuSelect,
 	MenuBack,
 	MenuMove,
-	_GameMusic,
+	GameMusic,
 }
 
 type PlayEntry = (usize, SoundBase);
@@ -28,6 +28,12 @@ pub struct Audio {
 	playing: HashMap<PlayEntry, StaticSoundHandle>,
 }
 
+impl Default for Audio {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl Audio {
 	pub fn new() -> Audio {
 		let mut audio = Audio {
@@ -68,6 +74,25 @@ impl Audio {
 		self.id_counter - 1
 	}
 
+	/// Plays a looping music track from `assets/audio/music`, the track is loaded when played
+	pub fn play_music(&mut self, file_name: &str) -> Option<usize> {
+		let path = Path::new("./assets/audio/music").join(file_name);
+		let settings = StaticSoundSettings::new().loop_region(..);
+		let data = match StaticSoundData::from_file(&path, settings) {
+			Ok(data) => data,
+			Err(err) => {
+				log::warn!("Can't load music '{}': {err}", path.display());
+				return None;
+			},
+		};
+		let handle = self.manager.play(data).unwrap();
+		self
+			.playing
+			.insert((self.id_counter, SoundBase::GameMusic), handle);
+		self.id_counter += 1;
+		Some(self.id_counter - 1)
+	}
+
 	pub fn _stop_sound(&mut self, entry: &PlayEntry) {
 		if let Some(mut handle) = self.playing.remove(entry) {
 			handle
@@ -88,8 +113,32 @@ impl Audio {
 		});
 	}
 
-	pub fn _stop_sound_by_type(&mut self, sound_type: SoundBase) {
-		self._stop_sound_condition(|(_, sound)| sound != &sound_type);
+	pub fn stop_sound_by_type(&mut self, sound_type: SoundBase) {
+		self._stop_sound_condition(|(_, sound)| sound == &sound_type);
+	}
+
+	/// Pauses the sounds playing, sounds played after are not affected
+	pub fn pause_all(&mut self) {
+		for handle in self.playing.values_mut() {
+			if handle.state() == PlaybackState::Playing {
+				handle
+					.pause(Tween { duration: Duration::from_micros(10), ..Default::default() })
+					.unwrap();
+			}
+		}
+	}
+
+	pub fn resume_all(&mut self) {
+		for handle in self.playing.values_mut() {
+			if matches!(
+				handle.state(),
+				PlaybackState::Paused | PlaybackState::Pausing
+			) {
+				handle
+					.resume(Tween { duration: Duration::from_micros(10), ..Default::default() })
+					.unwrap();
+			}
+		}
 	}
 
 	pub fn delete_ended_sounds(&mut self) {